@@ -0,0 +1,112 @@
+//! Cancelling functions marked with `#[async(cancellable)]`
+
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    waiting: Vec<Completer<()>>,
+    children: Vec<Weak<RefCell<State>>>,
+}
+
+/// Tells the async functions it is passed to to stop. A function marked with
+/// `#[async(cancellable)]` checks its argument of this type whenever it continues after
+/// an await, and completes with `Err(Cancelled)` instead of running the rest of its body.
+/// Cloning it creates another handle to the same token.
+///
+/// ```rust,ignore
+/// #[async(cancellable)]
+/// fn poll_updates(token: CancellationToken) -> u32 {
+///     let mut updates = 0;
+///     loop {
+///         await!(async_runtime::sleep(Duration::from_secs(1)));
+///         updates += 1;
+///     }
+/// }
+///
+/// let token = CancellationToken::new();
+/// poll_updates(token.clone(), |result| assert_eq!(result, Err(Cancelled)));
+/// token.cancel();
+/// ```
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Rc<RefCell<State>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the token and every child token created from it
+    pub fn cancel(&self) {
+        let (waiting, children) = {
+            let mut state = self.state.borrow_mut();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            (mem::take(&mut state.waiting), mem::take(&mut state.children))
+        };
+
+        for waiting in waiting {
+            waiting.complete(());
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            CancellationToken { state: child }.cancel();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.borrow().cancelled
+    }
+
+    /// A token that is cancelled along with this one, but can also be cancelled on its own
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.state.borrow_mut();
+        if state.cancelled {
+            child.state.borrow_mut().cancelled = true;
+        } else {
+            state.children.retain(|child| child.strong_count() > 0);
+            state.children.push(Rc::downgrade(&child.state));
+        }
+        child
+    }
+
+    /// Completes once the token is cancelled
+    pub fn cancelled<F: FnOnce(()) + 'static>(&self, callback: F) {
+        let waiting = executor::pending(callback);
+        let mut state = self.state.borrow_mut();
+        if state.cancelled {
+            waiting.complete(());
+        } else {
+            state.waiting.push(waiting);
+        }
+    }
+}
+
+/// The function was cancelled through its `CancellationToken` before it completed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("operation was cancelled")
+    }
+}
+
+impl Error for Cancelled {}
+
+/// Lets functions returning `io::Result` use `?` on the result of a cancellable function
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> io::Error {
+        io::Error::new(io::ErrorKind::Interrupted, cancelled)
+    }
+}
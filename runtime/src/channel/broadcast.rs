@@ -0,0 +1,189 @@
+//! Channel whose values are received by every receiver
+
+use super::SendError;
+use executor::{self, Completer};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+/// Position of a waiting receiver and how to hand it the next value
+type Receiving<T> = (Rc<Cursor>, Completer<Result<T, RecvError>>);
+
+struct Shared<T> {
+    /// The last values sent, the first one has the number `first`
+    values: VecDeque<T>,
+    first: u64,
+    capacity: usize,
+    receiving: Vec<Receiving<T>>,
+    senders: usize,
+    /// Number of cursors, clones of a receiver share one
+    receivers: Rc<Cell<usize>>,
+}
+
+/// Number of the next value a receiver takes out. A waiting `recv` holds on to
+/// it too, so the receiver only goes away once the cursor is dropped.
+struct Cursor {
+    next: Cell<u64>,
+    receivers: Rc<Cell<usize>>,
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        self.receivers.set(self.receivers.get() - 1);
+    }
+}
+
+impl<T: Clone + 'static> Shared<T> {
+    /// The next value for the cursor, `None` if it has to wait for one
+    fn next(&self, cursor: &Cursor) -> Option<Result<T, RecvError>> {
+        let next = cursor.next.get();
+        if next < self.first {
+            cursor.next.set(self.first);
+            return Some(Err(RecvError::Lagged(self.first - next)));
+        }
+        match self.values.get((next - self.first) as usize) {
+            Some(value) => {
+                cursor.next.set(next + 1);
+                Some(Ok(value.clone()))
+            }
+            None if self.senders == 0 => Some(Err(RecvError::Closed)),
+            None => None,
+        }
+    }
+
+    fn tail(&self) -> u64 {
+        self.first + self.values.len() as u64
+    }
+}
+
+/// Creates a channel that keeps the last `capacity` values for receivers that fall behind
+pub fn channel<T: Clone + 'static>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "the capacity of a channel can't be zero");
+    let shared = Rc::new(RefCell::new(Shared {
+        values: VecDeque::with_capacity(capacity),
+        first: 0,
+        capacity,
+        receiving: Vec::new(),
+        senders: 1,
+        receivers: Rc::new(Cell::new(0)),
+    }));
+    let receiver = Receiver::new(shared.clone(), 0);
+    (Sender { shared }, receiver)
+}
+
+/// Why a receiver didn't get a value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender is gone and the receiver got all values
+    Closed,
+    /// The receiver fell behind, that many values were dropped before it got them.
+    /// It continues with the oldest value that is left.
+    Lagged(u64),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RecvError::Closed => f.write_str("channel closed"),
+            RecvError::Lagged(count) => write!(f, "receiver lagged behind by {} values", count),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+pub struct Sender<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: Clone + 'static> Sender<T> {
+    /// Sends the value to every receiver, returning how many there are.
+    /// Never waits, the oldest value is dropped once the channel is full.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.receivers.get() == 0 {
+            return Err(SendError(value));
+        }
+        if shared.values.len() == shared.capacity {
+            shared.values.pop_front();
+            shared.first += 1;
+        }
+        shared.values.push_back(value);
+
+        for (cursor, receiving) in std::mem::take(&mut shared.receiving) {
+            // Waiting receivers got every value before, the new one is theirs
+            let value = shared.next(&cursor).expect("a value was just sent");
+            receiving.complete(value);
+        }
+        Ok(shared.receivers.get())
+    }
+
+    /// A receiver that gets the values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let tail = self.shared.borrow().tail();
+        Receiver::new(self.shared.clone(), tail)
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.shared.borrow().receivers.get()
+    }
+}
+
+impl<T: 'static> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().senders += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T: 'static> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            for (_, receiving) in shared.receiving.drain(..) {
+                receiving.complete(Err(RecvError::Closed));
+            }
+        }
+    }
+}
+
+/// Clones of a receiver share its position, `resubscribe` creates an independent one
+pub struct Receiver<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+    cursor: Rc<Cursor>,
+}
+
+impl<T: 'static> Receiver<T> {
+    fn new(shared: Rc<RefCell<Shared<T>>>, next: u64) -> Self {
+        let receivers = shared.borrow().receivers.clone();
+        receivers.set(receivers.get() + 1);
+        Receiver { shared, cursor: Rc::new(Cursor { next: Cell::new(next), receivers }) }
+    }
+}
+
+impl<T: Clone + 'static> Receiver<T> {
+    /// Completes with the next value, waiting for one to be sent
+    pub fn recv<F: FnOnce(Result<T, RecvError>) + 'static>(&self, callback: F) {
+        let completer = executor::pending(callback);
+        let mut shared = self.shared.borrow_mut();
+        match shared.next(&self.cursor) {
+            Some(value) => completer.complete(value),
+            None => shared.receiving.push((self.cursor.clone(), completer)),
+        }
+    }
+
+    /// A receiver that gets the values sent from now on
+    pub fn resubscribe(&self) -> Receiver<T> {
+        let tail = self.shared.borrow().tail();
+        Receiver::new(self.shared.clone(), tail)
+    }
+}
+
+impl<T: 'static> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver { shared: self.shared.clone(), cursor: self.cursor.clone() }
+    }
+}
@@ -0,0 +1,54 @@
+//! Channels for async functions running on the same event loop.
+//!
+//! Waiting operations take a callback as their last argument, so they can be awaited.
+//! Senders and receivers are cheap handles, clones of a receiver share its position,
+//! which is how a receiver can be used again in the continuation of `recv`:
+//!
+//! ```rust,ignore
+//! #[async]
+//! fn sum(receiver: mpsc::Receiver<u32>) -> u32 {
+//!     let mut sum = 0;
+//!     while let Some(value) = await!(receiver.clone().recv()) {
+//!         sum += value;
+//!     }
+//!     sum
+//! }
+//! ```
+
+use std::error::Error;
+use std::fmt;
+
+pub mod broadcast;
+pub mod mpsc;
+pub mod oneshot;
+pub mod watch;
+
+/// Every sender is gone, no value will arrive anymore
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+impl Error for RecvError {}
+
+/// Every receiver is gone, the value that couldn't be sent is handed back
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+impl<T> Error for SendError<T> {}
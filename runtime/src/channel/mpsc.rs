@@ -0,0 +1,204 @@
+//! Bounded channel with many senders and one receiver
+
+use super::SendError;
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+/// Value of a waiting sender and how to tell it that it was sent
+type Sending<T> = (T, Completer<Result<(), SendError<T>>>);
+
+struct Shared<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    /// Values of senders waiting for capacity, in the order they were sent
+    sending: VecDeque<Sending<T>>,
+    receiving: VecDeque<Completer<Option<T>>>,
+    senders: usize,
+    receivers: usize,
+}
+
+impl<T: 'static> Shared<T> {
+    // Operations that are waiting keep their side of the channel open, even after its handle was dropped
+
+    fn senders_gone(&self) -> bool {
+        self.senders == 0 && self.sending.is_empty()
+    }
+
+    fn receivers_gone(&self) -> bool {
+        self.receivers == 0 && self.receiving.is_empty()
+    }
+
+    /// Completes every operation that doesn't have to wait anymore
+    fn dispatch(&mut self) {
+        loop {
+            while self.queue.len() < self.capacity {
+                match self.sending.pop_front() {
+                    Some((value, sending)) => {
+                        self.queue.push_back(value);
+                        sending.complete(Ok(()));
+                    }
+                    None => break,
+                }
+            }
+            if self.queue.is_empty() || self.receiving.is_empty() {
+                break;
+            }
+            while !self.queue.is_empty() && !self.receiving.is_empty() {
+                let value = self.queue.pop_front();
+                self.receiving.pop_front().unwrap().complete(value);
+            }
+        }
+
+        if self.senders_gone() {
+            for receiving in self.receiving.drain(..) {
+                receiving.complete(None);
+            }
+        }
+        if self.receivers_gone() {
+            for (value, sending) in self.sending.drain(..) {
+                sending.complete(Err(SendError(value)));
+            }
+        }
+    }
+}
+
+/// Creates a channel that holds up to `capacity` values, senders wait while it is full
+pub fn channel<T: 'static>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "the capacity of a channel can't be zero");
+    let shared = Rc::new(RefCell::new(Shared {
+        queue: VecDeque::with_capacity(capacity),
+        capacity,
+        sending: VecDeque::new(),
+        receiving: VecDeque::new(),
+        senders: 1,
+        receivers: 1,
+    }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+/// Why `try_send` couldn't send a value, which is handed back
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full
+    Full(T),
+    /// Every receiver is gone
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrySendError::Full(_) => f.write_str("channel full"),
+            TrySendError::Closed(_) => f.write_str("channel closed"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+pub struct Sender<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: 'static> Sender<T> {
+    /// Completes once the value is in the channel, waiting while it is full.
+    /// Fails if every receiver is gone.
+    pub fn send<F: FnOnce(Result<(), SendError<T>>) + 'static>(&self, value: T, callback: F) {
+        let mut shared = self.shared.borrow_mut();
+        shared.sending.push_back((value, executor::pending(callback)));
+        shared.dispatch();
+    }
+
+    /// Puts the value into the channel if there is room without waiting
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.receivers_gone() {
+            return Err(TrySendError::Closed(value));
+        }
+        // Senders that are already waiting come first
+        if shared.queue.len() >= shared.capacity || !shared.sending.is_empty() {
+            return Err(TrySendError::Full(value));
+        }
+        shared.queue.push_back(value);
+        shared.dispatch();
+        Ok(())
+    }
+
+    /// Whether every receiver was dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.borrow().receivers_gone()
+    }
+}
+
+impl<T: 'static> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().senders += 1;
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T: 'static> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.senders -= 1;
+        shared.dispatch();
+    }
+}
+
+/// Clones of a receiver take values out of the same channel, each value goes to one of them
+pub struct Receiver<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: 'static> Receiver<T> {
+    /// Completes with the next value, or `None` once every sender is gone and the channel is empty
+    pub fn recv<F: FnOnce(Option<T>) + 'static>(&self, callback: F) {
+        let mut shared = self.shared.borrow_mut();
+        shared.receiving.push_back(executor::pending(callback));
+        shared.dispatch();
+    }
+
+    /// Takes out the next value if there is one without waiting
+    pub fn try_recv(&self) -> Option<T> {
+        let mut shared = self.shared.borrow_mut();
+        if !shared.receiving.is_empty() {
+            return None;
+        }
+        let value = shared.queue.pop_front();
+        shared.dispatch();
+        value
+    }
+
+    /// Whether every sender was dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.borrow().senders_gone()
+    }
+}
+
+impl<T: 'static> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().receivers += 1;
+        Receiver { shared: self.shared.clone() }
+    }
+}
+
+impl<T: 'static> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.receivers -= 1;
+        shared.dispatch();
+    }
+}
@@ -0,0 +1,80 @@
+//! Channel for a single value
+
+use super::RecvError;
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct Shared<T> {
+    value: Option<T>,
+    waiting: Option<Completer<Result<T, RecvError>>>,
+    sender: bool,
+    receiver: bool,
+}
+
+/// Creates a channel that carries one value from the sender to the receiver
+pub fn channel<T: 'static>() -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(RefCell::new(Shared { value: None, waiting: None, sender: true, receiver: true }));
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+pub struct Sender<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: 'static> Sender<T> {
+    /// Hands the value to the receiver, giving it back if the receiver is gone
+    pub fn send(self, value: T) -> Result<(), T> {
+        let mut shared = self.shared.borrow_mut();
+        if !shared.receiver {
+            return Err(value);
+        }
+        match shared.waiting.take() {
+            Some(waiting) => waiting.complete(Ok(value)),
+            None => shared.value = Some(value),
+        }
+        Ok(())
+    }
+
+    /// Whether the receiver was dropped
+    pub fn is_closed(&self) -> bool {
+        !self.shared.borrow().receiver
+    }
+}
+
+impl<T: 'static> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.sender = false;
+        if let Some(waiting) = shared.waiting.take() {
+            waiting.complete(Err(RecvError));
+        }
+    }
+}
+
+pub struct Receiver<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: 'static> Receiver<T> {
+    /// Completes with the value, or an error if the sender was dropped without sending one
+    pub fn recv<F: FnOnce(Result<T, RecvError>) + 'static>(self, callback: F) {
+        let completer = executor::pending(callback);
+        let mut shared = self.shared.borrow_mut();
+        match shared.value.take() {
+            Some(value) => completer.complete(Ok(value)),
+            None if !shared.sender => completer.complete(Err(RecvError)),
+            None => shared.waiting = Some(completer),
+        }
+    }
+}
+
+impl<T: 'static> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // `recv` consumes the receiver, the channel stays open while it waits
+        let mut shared = self.shared.borrow_mut();
+        if shared.waiting.is_none() {
+            shared.receiver = false;
+        }
+    }
+}
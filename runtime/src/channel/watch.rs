@@ -0,0 +1,141 @@
+//! Channel that holds a single value, receivers are told when it changes
+
+use super::{RecvError, SendError};
+use executor::{self, Completer};
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+
+/// Receiver waiting for a change and how to tell it
+type Changing = (Rc<Seen>, Completer<Result<(), RecvError>>);
+
+struct Shared<T> {
+    value: T,
+    /// Incremented by every change of the value
+    version: u64,
+    changing: Vec<Changing>,
+    sender: bool,
+    receivers: Rc<Cell<usize>>,
+}
+
+/// The version a receiver has seen, shared by its clones like the cursor of a broadcast receiver
+struct Seen {
+    version: Cell<u64>,
+    receivers: Rc<Cell<usize>>,
+}
+
+impl Drop for Seen {
+    fn drop(&mut self) {
+        self.receivers.set(self.receivers.get() - 1);
+    }
+}
+
+/// Creates a channel holding `initial`, which receivers have already seen
+pub fn channel<T: 'static>(initial: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(RefCell::new(Shared {
+        value: initial,
+        version: 0,
+        changing: Vec::new(),
+        sender: true,
+        receivers: Rc::new(Cell::new(0)),
+    }));
+    let receiver = Receiver::new(shared.clone());
+    (Sender { shared }, receiver)
+}
+
+pub struct Sender<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+}
+
+impl<T: 'static> Sender<T> {
+    /// Replaces the value and tells every waiting receiver. Fails if every receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.is_closed() {
+            return Err(SendError(value));
+        }
+        let mut shared = self.shared.borrow_mut();
+        shared.value = value;
+        shared.version += 1;
+
+        let version = shared.version;
+        for (seen, changing) in mem::take(&mut shared.changing) {
+            seen.version.set(version);
+            changing.complete(Ok(()));
+        }
+        Ok(())
+    }
+
+    /// A receiver that has seen the current value
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver::new(self.shared.clone())
+    }
+
+    /// Whether every receiver was dropped
+    pub fn is_closed(&self) -> bool {
+        self.shared.borrow().receivers.get() == 0
+    }
+}
+
+impl<T: 'static> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.sender = false;
+        for (_, changing) in shared.changing.drain(..) {
+            changing.complete(Err(RecvError));
+        }
+    }
+}
+
+/// Clones of a receiver share which version they have seen
+pub struct Receiver<T: 'static> {
+    shared: Rc<RefCell<Shared<T>>>,
+    seen: Rc<Seen>,
+}
+
+impl<T: 'static> Receiver<T> {
+    fn new(shared: Rc<RefCell<Shared<T>>>) -> Self {
+        let (version, receivers) = {
+            let shared = shared.borrow();
+            (shared.version, shared.receivers.clone())
+        };
+        receivers.set(receivers.get() + 1);
+        Receiver { shared, seen: Rc::new(Seen { version: Cell::new(version), receivers }) }
+    }
+
+    /// Completes once the value changed since the receiver last saw it, marking it as seen.
+    /// Fails if the sender is gone, changes made before it was dropped are reported first.
+    pub fn changed<F: FnOnce(Result<(), RecvError>) + 'static>(&self, callback: F) {
+        let completer = executor::pending(callback);
+        let mut shared = self.shared.borrow_mut();
+        if self.seen.version.get() != shared.version {
+            self.seen.version.set(shared.version);
+            completer.complete(Ok(()));
+        } else if !shared.sender {
+            completer.complete(Err(RecvError));
+        } else {
+            shared.changing.push((self.seen.clone(), completer));
+        }
+    }
+
+    /// Marks the current value as seen and returns a copy of it
+    pub fn get_and_update(&self) -> T
+        where T: Clone
+    {
+        let shared = self.shared.borrow();
+        self.seen.version.set(shared.version);
+        shared.value.clone()
+    }
+
+    /// A copy of the current value, without marking it as seen
+    pub fn get(&self) -> T
+        where T: Clone
+    {
+        self.shared.borrow().value.clone()
+    }
+}
+
+impl<T: 'static> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Receiver { shared: self.shared.clone(), seen: self.seen.clone() }
+    }
+}
@@ -0,0 +1,340 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::task::{Wake, Waker};
+use reactor::{Interest, Notify, Reactor};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+use timer::Wheel;
+
+type Callback = Box<dyn FnOnce()>;
+type RemoteCallback = Box<dyn FnOnce(Box<dyn Any + Send>)>;
+
+/// Sent to the event loop from other threads
+enum Message {
+    /// Result of a remote operation, `None` if its completer was dropped
+    Complete(usize, Option<Box<dyn Any + Send>>),
+    /// A task has to be polled again
+    Wake(usize),
+}
+
+thread_local!(static CURRENT: RefCell<Option<Rc<Core>>> = const { RefCell::new(None) });
+
+/// State of the event loop running on the current thread
+struct Core {
+    /// Callbacks that can run on the next turn
+    ready: RefCell<VecDeque<Callback>>,
+    /// Callbacks of operations completed by other threads
+    remote: RefCell<HashMap<usize, RemoteCallback>>,
+    /// Polls futures driven by the event loop, run again whenever they are woken
+    tasks: RefCell<HashMap<usize, Rc<dyn Fn()>>>,
+    timers: RefCell<Wheel>,
+    reactor: Reactor,
+    next_id: Cell<usize>,
+    sender: Remote,
+    receiver: Receiver<Message>,
+}
+
+/// Sends messages to the event loop, waking it up if it waits for file descriptors
+#[derive(Clone)]
+struct Remote {
+    sender: Sender<Message>,
+    notify: Arc<Notify>,
+}
+
+impl Remote {
+    /// Nothing happens if the event loop is gone
+    fn send(&self, message: Message) {
+        if self.sender.send(message).is_ok() {
+            self.notify.wake();
+        }
+    }
+}
+
+impl Core {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let reactor = Reactor::new().expect("failed to set up epoll for the event loop");
+        let notify = reactor.notify();
+
+        Core {
+            ready: RefCell::new(VecDeque::new()),
+            remote: RefCell::new(HashMap::new()),
+            tasks: RefCell::new(HashMap::new()),
+            timers: RefCell::new(Wheel::new(Instant::now())),
+            reactor,
+            next_id: Cell::new(0),
+            sender: Remote { sender, notify },
+            receiver,
+        }
+    }
+
+    fn next_id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// Runs callbacks until nothing is ready and no remote operation, task, timer or
+    /// file descriptor is pending
+    fn run_until_idle(&self) {
+        loop {
+            self.receive_remote(self.receiver.try_recv().ok());
+            let fired = self.timers.borrow_mut().advance(Instant::now());
+            self.ready.borrow_mut().extend(fired);
+            if !self.reactor.is_idle() {
+                let ready = self.reactor.poll(Some(Duration::from_millis(0)));
+                self.ready.borrow_mut().extend(ready);
+            }
+
+            // Callbacks scheduled while the batch runs wait for the next turn
+            let batch = mem::take(&mut *self.ready.borrow_mut());
+            if batch.is_empty() {
+                if self.remote.borrow().is_empty() && self.tasks.borrow().is_empty() &&
+                   self.timers.borrow().is_empty() && self.reactor.is_idle() {
+                    return;
+                }
+
+                // Sleeps until a file descriptor is ready, a message arrives or the next timer is due
+                let deadline = self.timers.borrow().next_deadline();
+                let ready = self.reactor.poll(deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())));
+                self.ready.borrow_mut().extend(ready);
+                continue;
+            }
+
+            for callback in batch {
+                callback();
+            }
+        }
+    }
+
+    /// Queues the callbacks of remote operations that completed and the tasks that were woken,
+    /// starting with `next` and followed by the messages that already arrived
+    fn receive_remote(&self, mut next: Option<Message>) {
+        while let Some(message) = next {
+            match message {
+                Message::Complete(id, value) => {
+                    let callback = self.remote.borrow_mut().remove(&id);
+                    if let (Some(callback), Some(value)) = (callback, value) {
+                        self.ready.borrow_mut().push_back(Box::new(move || callback(value)));
+                    }
+                }
+                Message::Wake(id) => {
+                    let task = self.tasks.borrow().get(&id).cloned();
+                    if let Some(task) = task {
+                        self.ready.borrow_mut().push_back(Box::new(move || task()));
+                    }
+                }
+            }
+            next = self.receiver.try_recv().ok();
+        }
+    }
+
+    fn register_remote<T: Send + 'static>(&self, callback: Box<dyn FnOnce(T)>) -> RemoteCompleter<T> {
+        let id = self.next_id();
+        self.remote.borrow_mut().insert(id, Box::new(move |value: Box<dyn Any + Send>| {
+            match value.downcast::<T>() {
+                Ok(value) => callback(*value),
+                Err(_) => unreachable!("remote completers send values of their own type"),
+            }
+        }));
+
+        RemoteCompleter {
+            id,
+            sender: Some(self.sender.clone()),
+            marker: PhantomData,
+        }
+    }
+}
+
+fn with_core<R, F: FnOnce(&Core) -> R>(f: F) -> R {
+    match CURRENT.with(|current| current.borrow().clone()) {
+        Some(core) => f(&core),
+        None => panic!("no event loop is running on this thread, start one with async_runtime::run"),
+    }
+}
+
+/// Whether an event loop is running on this thread
+pub(crate) fn is_running() -> bool {
+    CURRENT.with(|current| current.borrow().is_some())
+}
+
+/// Clears the event loop of the thread once `run` returns or panics
+struct Reset;
+
+impl Drop for Reset {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
+    }
+}
+
+/// Calls `f` on a new event loop, then runs the callbacks it schedules
+/// until there is no work left
+pub fn run<F: FnOnce()>(f: F) {
+    let core = Rc::new(Core::new());
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        assert!(current.is_none(), "async_runtime::run can't be called from inside of an event loop");
+        *current = Some(core.clone());
+    });
+    let _reset = Reset;
+
+    f();
+    core.run_until_idle();
+}
+
+/// Runs an async function on a new event loop and returns the value it completes with
+///
+/// ```rust,ignore
+/// let user = async_runtime::block_on(|callback| get_user(1, callback));
+/// ```
+pub fn block_on<T, F>(f: F) -> T
+    where T: 'static,
+          F: FnOnce(Box<dyn FnOnce(T)>)
+{
+    let result = Rc::new(RefCell::new(None));
+    let slot = result.clone();
+    run(move || f(Box::new(move |value| *slot.borrow_mut() = Some(value))));
+
+    let value = result.borrow_mut().take();
+    value.expect("the event loop ran out of work before the async function completed")
+}
+
+/// Queues a callback to run on the next turn of the event loop
+pub fn schedule<F: FnOnce() + 'static>(f: F) {
+    with_core(|core| core.ready.borrow_mut().push_back(Box::new(f)));
+}
+
+/// Lets the other ready callbacks run before continuing
+///
+/// ```rust,ignore
+/// await!(async_runtime::yield_now());
+/// ```
+pub fn yield_now<F: FnOnce(()) + 'static>(callback: F) {
+    schedule(move || callback(()));
+}
+
+/// Starts an operation that completes later. `callback` receives the result
+/// once it is passed to the returned completer.
+pub fn pending<T, F>(callback: F) -> Completer<T>
+    where T: 'static,
+          F: FnOnce(T) + 'static
+{
+    Completer { callback: Box::new(callback) }
+}
+
+/// Completes an operation on the thread of the event loop
+pub struct Completer<T> {
+    callback: Box<dyn FnOnce(T)>,
+}
+
+impl<T: 'static> Completer<T> {
+    /// Queues the callback of the operation with its result
+    pub fn complete(self, value: T) {
+        let callback = self.callback;
+        schedule(move || callback(value));
+    }
+
+    /// Turns the completer into one that can be sent to another thread.
+    /// The event loop keeps running until it is completed or dropped.
+    pub fn remote(self) -> RemoteCompleter<T>
+        where T: Send
+    {
+        with_core(|core| core.register_remote(self.callback))
+    }
+}
+
+/// Completes an operation from any thread
+pub struct RemoteCompleter<T> {
+    id: usize,
+    sender: Option<Remote>,
+    marker: PhantomData<fn(T)>,
+}
+
+impl<T: Send + 'static> RemoteCompleter<T> {
+    /// Hands the result to the event loop, which queues the callback of the operation
+    pub fn complete(mut self, value: T) {
+        if let Some(sender) = self.sender.take() {
+            // Nobody waits for the result anymore if the event loop is gone
+            sender.send(Message::Complete(self.id, Some(Box::new(value))));
+        }
+    }
+}
+
+impl<T> Drop for RemoteCompleter<T> {
+    fn drop(&mut self) {
+        // Lets the event loop stop waiting for the operation
+        if let Some(sender) = self.sender.take() {
+            sender.send(Message::Complete(self.id, None));
+        }
+    }
+}
+
+/// Wakes a task from any thread by sending a message to its event loop
+struct TaskWaker {
+    id: usize,
+    sender: Remote,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.sender.send(Message::Wake(self.id));
+    }
+}
+
+/// Reserves a task on the event loop, returning its id and the waker that has it polled again
+pub(crate) fn new_task() -> (usize, Waker) {
+    with_core(|core| {
+        let id = core.next_id();
+        let waker = Waker::from(Arc::new(TaskWaker { id, sender: core.sender.clone() }));
+        (id, waker)
+    })
+}
+
+/// Registers how a task is polled and polls it on the next turn.
+/// The event loop keeps running until the task is finished.
+pub(crate) fn start_task(id: usize, poll: Rc<dyn Fn()>) {
+    with_core(|core| {
+        core.tasks.borrow_mut().insert(id, poll.clone());
+        core.ready.borrow_mut().push_back(Box::new(move || poll()));
+    });
+}
+
+pub(crate) fn finish_task(id: usize) {
+    with_core(|core| core.tasks.borrow_mut().remove(&id));
+}
+
+/// Calls `callback` on the first turn after `deadline`, returns the id to cancel it with
+pub(crate) fn add_timer(deadline: Instant, callback: Box<dyn FnOnce()>) -> usize {
+    with_core(|core| {
+        let id = core.next_id();
+        core.timers.borrow_mut().insert(id, deadline, callback);
+        id
+    })
+}
+
+pub(crate) fn cancel_timer(id: usize) {
+    with_core(|core| core.timers.borrow_mut().cancel(id));
+}
+
+/// Calls `callback` on the first turn after `fd` is ready
+pub(crate) fn wait_fd(fd: RawFd, interest: Interest, callback: Box<dyn FnOnce()>) -> io::Result<()> {
+    with_core(|core| core.reactor.wait(fd, interest, callback))
+}
+
+/// Forgets the file descriptor, nothing happens if the event loop is already gone
+pub(crate) fn deregister_fd(fd: RawFd) {
+    if let Some(core) = CURRENT.with(|current| current.borrow().clone()) {
+        core.reactor.deregister(fd);
+    }
+}
@@ -0,0 +1,133 @@
+//! File system operations run on worker threads, completing on the event loop.
+//! They mirror the functions of `std::fs` with the callback as their last argument.
+//!
+//! ```rust,ignore
+//! let config = await!(async_runtime::fs::read("config.toml"))?;
+//! ```
+
+use pool;
+use std::fs::{self, DirEntry, Metadata, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Reads the whole file
+pub fn read<P, F>(path: P, callback: F)
+    where P: AsRef<Path>,
+          F: FnOnce(io::Result<Vec<u8>>) + 'static
+{
+    let path = path.as_ref().to_owned();
+    pool::spawn_blocking(move || fs::read(path), callback);
+}
+
+/// Writes the contents to a file, replacing it if it exists
+pub fn write<P, C, F>(path: P, contents: C, callback: F)
+    where P: AsRef<Path>,
+          C: AsRef<[u8]> + Send + 'static,
+          F: FnOnce(io::Result<()>) + 'static
+{
+    let path = path.as_ref().to_owned();
+    pool::spawn_blocking(move || fs::write(path, contents), callback);
+}
+
+pub fn metadata<P, F>(path: P, callback: F)
+    where P: AsRef<Path>,
+          F: FnOnce(io::Result<Metadata>) + 'static
+{
+    let path = path.as_ref().to_owned();
+    pool::spawn_blocking(move || fs::metadata(path), callback);
+}
+
+/// Lists the entries of a directory
+pub fn read_dir<P, F>(path: P, callback: F)
+    where P: AsRef<Path>,
+          F: FnOnce(io::Result<Vec<DirEntry>>) + 'static
+{
+    let path = path.as_ref().to_owned();
+    pool::spawn_blocking(move || fs::read_dir(path)?.collect(), callback);
+}
+
+/// Copies a file, completing with the number of bytes copied
+pub fn copy<P, Q, F>(from: P, to: Q, callback: F)
+    where P: AsRef<Path>,
+          Q: AsRef<Path>,
+          F: FnOnce(io::Result<u64>) + 'static
+{
+    let (from, to) = (from.as_ref().to_owned(), to.as_ref().to_owned());
+    pool::spawn_blocking(move || fs::copy(from, to), callback);
+}
+
+/// Opens a file for reading
+pub fn open<P, F>(path: P, callback: F)
+    where P: AsRef<Path>,
+          F: FnOnce(io::Result<File>) + 'static
+{
+    File::open_with(path.as_ref().to_owned(), OpenOptions::new().read(true).clone(), callback);
+}
+
+/// Open file whose operations run on worker threads.
+/// It's a cheap handle, clones refer to the same file.
+#[derive(Clone)]
+pub struct File {
+    inner: Arc<Mutex<fs::File>>,
+}
+
+impl File {
+    /// Opens a file for reading
+    pub fn open<P, F>(path: P, callback: F)
+        where P: AsRef<Path>,
+              F: FnOnce(io::Result<File>) + 'static
+    {
+        open(path, callback);
+    }
+
+    /// Opens a file for writing, creating it or truncating it if it exists
+    pub fn create<P, F>(path: P, callback: F)
+        where P: AsRef<Path>,
+              F: FnOnce(io::Result<File>) + 'static
+    {
+        let options = OpenOptions::new().write(true).create(true).truncate(true).clone();
+        File::open_with(path.as_ref().to_owned(), options, callback);
+    }
+
+    /// Opens a file with the given options
+    pub fn open_with<F>(path: PathBuf, options: OpenOptions, callback: F)
+        where F: FnOnce(io::Result<File>) + 'static
+    {
+        let open = move || options.open(path).map(|file| File { inner: Arc::new(Mutex::new(file)) });
+        pool::spawn_blocking(open, callback);
+    }
+
+    /// Reads up to `max` bytes from the current position, an empty buffer means the end was reached
+    pub fn read<F: FnOnce(io::Result<Vec<u8>>) + 'static>(&self, max: usize, callback: F) {
+        let inner = self.inner.clone();
+        pool::spawn_blocking(move || {
+            let mut buf = vec![0; max];
+            let len = lock(&inner).read(&mut buf)?;
+            buf.truncate(len);
+            Ok(buf)
+        }, callback);
+    }
+
+    pub fn write_all<F: FnOnce(io::Result<()>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        let inner = self.inner.clone();
+        pool::spawn_blocking(move || lock(&inner).write_all(&data), callback);
+    }
+
+    /// Flushes the data and metadata to the disk
+    pub fn sync_all<F: FnOnce(io::Result<()>) + 'static>(&self, callback: F) {
+        let inner = self.inner.clone();
+        pool::spawn_blocking(move || lock(&inner).sync_all(), callback);
+    }
+
+    pub fn metadata<F: FnOnce(io::Result<Metadata>) + 'static>(&self, callback: F) {
+        let inner = self.inner.clone();
+        pool::spawn_blocking(move || lock(&inner).metadata(), callback);
+    }
+}
+
+/// Operations on the same file never run at the same time, even on different workers
+fn lock(file: &Mutex<fs::File>) -> MutexGuard<'_, fs::File> {
+    // A panicking operation can't leave the file in an inconsistent state
+    file.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,122 @@
+//! Adapters between callback style async functions and `std::future::Future`.
+//! `#[async(future)]` functions return an `AsyncFn`, `await!` on anything that isn't
+//! a function call drives it with `await_future`.
+
+use executor;
+use std::cell::RefCell;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+/// Future that starts the converted body of an async function when it is first polled
+/// and completes once the body calls its final callback
+pub struct AsyncFn<T, F> {
+    state: State<T, F>,
+}
+
+enum State<T, F> {
+    /// Not polled yet
+    Start(F),
+    /// The body runs and will fill in the slot
+    Waiting(Rc<RefCell<Slot<T>>>),
+    Done,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl<T, F> AsyncFn<T, F>
+    where T: 'static,
+          F: FnOnce(Box<dyn FnOnce(T)>)
+{
+    pub fn new(body: F) -> Self {
+        AsyncFn { state: State::Start(body) }
+    }
+}
+
+// The body is moved out before it runs, it's never accessed through the pin
+impl<T, F> Unpin for AsyncFn<T, F> {}
+
+impl<T, F> Future for AsyncFn<T, F>
+    where T: 'static,
+          F: FnOnce(Box<dyn FnOnce(T)>)
+{
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let slot = match mem::replace(&mut self.state, State::Done) {
+            State::Start(body) => {
+                let slot = Rc::new(RefCell::new(Slot { value: None, waker: None }));
+                let result = slot.clone();
+                body(Box::new(move |value| {
+                    let waker = {
+                        let mut result = result.borrow_mut();
+                        result.value = Some(value);
+                        result.waker.take()
+                    };
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }));
+                slot
+            }
+            State::Waiting(slot) => slot,
+            State::Done => panic!("async function polled after it completed"),
+        };
+
+        let value = slot.borrow_mut().value.take();
+        match value {
+            Some(value) => Poll::Ready(value),
+            None => {
+                slot.borrow_mut().waker = Some(cx.waker().clone());
+                self.state = State::Waiting(slot);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Wraps a callback style call into a future, started when it is first polled
+///
+/// ```rust,ignore
+/// let future = async_runtime::future::from_callback(|callback| add_one(1, callback));
+/// ```
+pub fn from_callback<T, F>(call: F) -> AsyncFn<T, F>
+    where T: 'static,
+          F: FnOnce(Box<dyn FnOnce(T)>)
+{
+    AsyncFn::new(call)
+}
+
+/// Polls a future on the event loop until it completes, then calls `callback` with its output.
+/// Its waker can be used from any thread, each wake polls the future again on the next turn.
+pub fn await_future<F, C>(future: F, callback: C)
+    where F: Future + 'static,
+          C: FnOnce(F::Output) + 'static
+{
+    let (id, waker) = executor::new_task();
+    let task = Rc::new(RefCell::new(Some((Box::pin(future), callback))));
+
+    executor::start_task(id, Rc::new(move || {
+        let output = {
+            let mut task = task.borrow_mut();
+            let poll = match *task {
+                Some((ref mut future, _)) => future.as_mut().poll(&mut Context::from_waker(&waker)),
+                // Woken again after it completed
+                None => return,
+            };
+            match poll {
+                Poll::Ready(output) => (output, task.take().unwrap().1),
+                Poll::Pending => return,
+            }
+        };
+
+        executor::finish_task(id);
+        let (output, callback) = output;
+        callback(output);
+    }));
+}
@@ -0,0 +1,42 @@
+//! Event loop driving the callbacks generated by `#[async]`
+//!
+//! ```rust,ignore
+//! #[async]
+//! fn count() {
+//!     for i in 0..3 {
+//!         await!(async_runtime::yield_now());
+//!         println!("{}", i);
+//!     }
+//! }
+//!
+//! async_runtime::run(|| count());
+//! ```
+//!
+//! Callbacks never run on the stack of the operation that completes them,
+//! they are queued and run one after another by the event loop.
+//! While nothing is ready it waits for file descriptors with epoll, so it only runs on Linux.
+
+extern crate libc;
+
+mod cancel;
+pub mod channel;
+mod executor;
+pub mod fs;
+pub mod future;
+pub mod net;
+pub mod pipe;
+mod pool;
+mod reactor;
+mod scope;
+pub mod stream;
+pub mod sync;
+mod task;
+mod timer;
+pub mod trampoline;
+
+pub use cancel::{CancellationToken, Cancelled};
+pub use executor::{block_on, pending, run, schedule, yield_now, Completer, RemoteCompleter};
+pub use pool::{set_worker_threads, spawn_blocking};
+pub use scope::{scope, Scope};
+pub use task::{spawn, spawn_cancellable, AbortHandle, JoinError, JoinHandle};
+pub use timer::{interval, sleep, sleep_until, timeout, Elapsed, Interval};
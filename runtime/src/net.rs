@@ -0,0 +1,292 @@
+//! TCP, UDP and Unix domain sockets whose operations complete on the event loop.
+//!
+//! The types are cheap handles to the socket, cloning one is how it can be used
+//! in the continuation of one of its own operations:
+//!
+//! ```rust,ignore
+//! #[async]
+//! fn echo(stream: TcpStream) -> io::Result<()> {
+//!     loop {
+//!         let data = await!(stream.clone().read(1024))?;
+//!         if data.is_empty() {
+//!             return Ok(());
+//!         }
+//!         await!(stream.clone().write_all(data))?;
+//!     }
+//! }
+//! ```
+
+use executor;
+use libc;
+use pool;
+use reactor::{self, cvt, Interest, Source};
+use std::io;
+use std::mem;
+use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net as unix;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Listens for TCP connections
+#[derive(Clone)]
+pub struct TcpListener {
+    source: Rc<Source<net::TcpListener>>,
+}
+
+impl TcpListener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
+        let listener = net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener { source: Source::new(listener) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.source.get_ref().local_addr()
+    }
+
+    /// Completes with the next incoming connection
+    pub fn accept<F>(&self, callback: F)
+        where F: FnOnce(io::Result<(TcpStream, SocketAddr)>) + 'static
+    {
+        reactor::retry(self.source.clone(), Interest::Readable, |listener| listener.accept(), move |accepted| {
+            callback(accepted.and_then(|(stream, addr)| Ok((TcpStream::new(stream)?, addr))))
+        });
+    }
+}
+
+/// TCP connection
+#[derive(Clone)]
+pub struct TcpStream {
+    source: Rc<Source<net::TcpStream>>,
+}
+
+impl TcpStream {
+    fn new(stream: net::TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        Ok(TcpStream { source: Source::new(stream) })
+    }
+
+    /// Completes once the connection is established
+    pub fn connect<F>(addr: SocketAddr, callback: F)
+        where F: FnOnce(io::Result<TcpStream>) + 'static
+    {
+        let source = match start_connect(&addr) {
+            Ok(stream) => Source::new(stream),
+            Err(err) => return executor::schedule(move || callback(Err(err))),
+        };
+
+        // The socket becomes writable once connecting succeeded or failed
+        let connected = source.clone();
+        reactor::retry(source, Interest::Writable, |stream: &net::TcpStream| {
+            if let Some(err) = stream.take_error()? {
+                return Err(err);
+            }
+            match stream.peer_addr() {
+                Ok(_) => Ok(()),
+                Err(ref err) if err.kind() == io::ErrorKind::NotConnected => Err(io::ErrorKind::WouldBlock.into()),
+                Err(err) => Err(err),
+            }
+        }, move |result| callback(result.map(|()| TcpStream { source: connected })));
+    }
+
+    /// Reads up to `max` bytes, completing with an empty buffer once the other side closed the connection
+    pub fn read<F: FnOnce(io::Result<Vec<u8>>) + 'static>(&self, max: usize, callback: F) {
+        reactor::read(&self.source, max, callback);
+    }
+
+    /// Writes some of the data, completing with the number of bytes written
+    pub fn write<F: FnOnce(io::Result<usize>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write(&self.source, data, callback);
+    }
+
+    pub fn write_all<F: FnOnce(io::Result<()>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write_all(&self.source, data, callback);
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.source.get_ref().shutdown(how)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.source.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.source.get_ref().peer_addr()
+    }
+}
+
+/// Creates a non-blocking socket and starts connecting it
+fn start_connect(addr: &SocketAddr) -> io::Result<net::TcpStream> {
+    let domain = match *addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = cvt(unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) })?;
+    // Closes the socket if connecting fails
+    let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+
+    let (storage, len) = raw_addr(addr);
+    let result = unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) };
+    match cvt(result) {
+        Err(ref err) if err.raw_os_error() == Some(libc::EINPROGRESS) => Ok(stream),
+        Err(err) => Err(err),
+        Ok(_) => Ok(stream),
+    }
+}
+
+fn raw_addr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match *addr {
+        SocketAddr::V4(ref addr) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = addr.port().to_be();
+            raw.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(addr.ip().octets()) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(ref addr) => {
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = addr.port().to_be();
+            raw.sin6_flowinfo = addr.flowinfo();
+            raw.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            raw.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+/// UDP socket
+#[derive(Clone)]
+pub struct UdpSocket {
+    source: Rc<Source<net::UdpSocket>>,
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+        let socket = net::UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket { source: Source::new(socket) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.source.get_ref().local_addr()
+    }
+
+    /// Sends a datagram, completing with the number of bytes sent
+    pub fn send_to<F>(&self, data: Vec<u8>, addr: SocketAddr, callback: F)
+        where F: FnOnce(io::Result<usize>) + 'static
+    {
+        reactor::retry(self.source.clone(), Interest::Writable, move |socket| socket.send_to(&data, addr), callback);
+    }
+
+    /// Receives a datagram of up to `max` bytes, the rest of a longer one is discarded
+    pub fn recv_from<F>(&self, max: usize, callback: F)
+        where F: FnOnce(io::Result<(Vec<u8>, SocketAddr)>) + 'static
+    {
+        reactor::retry(self.source.clone(), Interest::Readable, move |socket| {
+            let mut buf = vec![0; max];
+            let (len, addr) = socket.recv_from(&mut buf)?;
+            buf.truncate(len);
+            Ok((buf, addr))
+        }, callback);
+    }
+
+    /// Sets the address `send` sends to and the only one `recv` receives from.
+    /// Connecting a UDP socket doesn't wait for the other side.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        self.source.get_ref().connect(addr)
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.source.get_ref().peer_addr()
+    }
+
+    /// Sends a datagram to the connected address, completing with the number of bytes sent
+    pub fn send<F: FnOnce(io::Result<usize>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::retry(self.source.clone(), Interest::Writable, move |socket| socket.send(&data), callback);
+    }
+
+    /// Receives a datagram of up to `max` bytes from the connected address
+    pub fn recv<F: FnOnce(io::Result<Vec<u8>>) + 'static>(&self, max: usize, callback: F) {
+        reactor::retry(self.source.clone(), Interest::Readable, move |socket| {
+            let mut buf = vec![0; max];
+            let len = socket.recv(&mut buf)?;
+            buf.truncate(len);
+            Ok(buf)
+        }, callback);
+    }
+}
+
+/// Listens for connections on a Unix domain socket
+#[derive(Clone)]
+pub struct UnixListener {
+    source: Rc<Source<unix::UnixListener>>,
+}
+
+impl UnixListener {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        let listener = unix::UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(UnixListener { source: Source::new(listener) })
+    }
+
+    /// Completes with the next incoming connection
+    pub fn accept<F: FnOnce(io::Result<UnixStream>) + 'static>(&self, callback: F) {
+        reactor::retry(self.source.clone(), Interest::Readable, |listener| listener.accept(), move |accepted| {
+            callback(accepted.and_then(|(stream, _)| UnixStream::new(stream)))
+        });
+    }
+}
+
+/// Connection over a Unix domain socket
+#[derive(Clone)]
+pub struct UnixStream {
+    source: Rc<Source<unix::UnixStream>>,
+}
+
+impl UnixStream {
+    fn new(stream: unix::UnixStream) -> io::Result<UnixStream> {
+        stream.set_nonblocking(true)?;
+        Ok(UnixStream { source: Source::new(stream) })
+    }
+
+    /// Connects to the socket at `path`. Connecting blocks while the backlog of the listener
+    /// is full, and a non-blocking connect can't be waited for then, so it runs on a worker thread.
+    pub fn connect<P, F>(path: P, callback: F)
+        where P: AsRef<Path>,
+              F: FnOnce(io::Result<UnixStream>) + 'static
+    {
+        let path = path.as_ref().to_path_buf();
+        pool::spawn_blocking(move || unix::UnixStream::connect(path), move |connected| {
+            callback(connected.and_then(UnixStream::new))
+        });
+    }
+
+    /// A pair of connected sockets
+    pub fn pair() -> io::Result<(UnixStream, UnixStream)> {
+        let (a, b) = unix::UnixStream::pair()?;
+        Ok((UnixStream::new(a)?, UnixStream::new(b)?))
+    }
+
+    /// Reads up to `max` bytes, completing with an empty buffer once the other side closed the connection
+    pub fn read<F: FnOnce(io::Result<Vec<u8>>) + 'static>(&self, max: usize, callback: F) {
+        reactor::read(&self.source, max, callback);
+    }
+
+    /// Writes some of the data, completing with the number of bytes written
+    pub fn write<F: FnOnce(io::Result<usize>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write(&self.source, data, callback);
+    }
+
+    pub fn write_all<F: FnOnce(io::Result<()>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write_all(&self.source, data, callback);
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.source.get_ref().shutdown(how)
+    }
+}
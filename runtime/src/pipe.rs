@@ -0,0 +1,46 @@
+//! Non-blocking pipes whose operations complete on the event loop
+
+use libc;
+use reactor::{self, cvt, Source};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::FromRawFd;
+use std::rc::Rc;
+
+/// Creates a pipe, data written to the writer can be read from the reader
+pub fn pipe() -> io::Result<(PipeReader, PipeWriter)> {
+    let mut fds = [0; 2];
+    cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) })?;
+    let (reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    Ok((PipeReader { source: Source::new(reader) }, PipeWriter { source: Source::new(writer) }))
+}
+
+/// Reading end of a pipe
+#[derive(Clone)]
+pub struct PipeReader {
+    source: Rc<Source<File>>,
+}
+
+impl PipeReader {
+    /// Reads up to `max` bytes, completing with an empty buffer once every writer is closed
+    pub fn read<F: FnOnce(io::Result<Vec<u8>>) + 'static>(&self, max: usize, callback: F) {
+        reactor::read(&self.source, max, callback);
+    }
+}
+
+/// Writing end of a pipe
+#[derive(Clone)]
+pub struct PipeWriter {
+    source: Rc<Source<File>>,
+}
+
+impl PipeWriter {
+    /// Writes some of the data, completing with the number of bytes written
+    pub fn write<F: FnOnce(io::Result<usize>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write(&self.source, data, callback);
+    }
+
+    pub fn write_all<F: FnOnce(io::Result<()>) + 'static>(&self, data: Vec<u8>, callback: F) {
+        reactor::write_all(&self.source, data, callback);
+    }
+}
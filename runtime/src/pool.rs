@@ -0,0 +1,91 @@
+//! Worker threads running blocking work off the event loop
+
+use executor;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+/// Number of worker threads, they are started when the first job is submitted.
+/// Starting them takes the number and leaves `STARTED` in its place.
+static WORKERS: AtomicUsize = AtomicUsize::new(4);
+
+const STARTED: usize = 0;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+static POOL: OnceLock<Sender<Job>> = OnceLock::new();
+
+fn start() -> Sender<Job> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for i in 0..WORKERS.swap(STARTED, Ordering::SeqCst) {
+        let receiver = receiver.clone();
+        thread::Builder::new()
+            .name(format!("async-runtime-worker-{}", i))
+            .spawn(move || work(&receiver))
+            .expect("failed to start a worker thread");
+    }
+    sender
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            // Jobs catch panics of the work themselves, this keeps the worker alive regardless
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+/// Sets the number of worker threads running blocking work, 4 by default.
+/// Has to be called before the first blocking operation starts them.
+///
+/// # Panics
+///
+/// If `count` is 0 or the worker threads are already running
+pub fn set_worker_threads(count: usize) {
+    assert!(count > 0, "at least one worker thread is needed");
+    // Checked and stored at once, so the workers can't start in between
+    let stored = WORKERS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+        if workers == STARTED { None } else { Some(count) }
+    });
+    assert!(stored.is_ok(), "the worker threads are already running");
+}
+
+/// Runs synchronous code on a worker thread, so it doesn't hold up the event loop.
+/// `callback` receives its result on the event loop. Jobs wait for a free worker
+/// once all of them are busy.
+///
+/// ```rust,ignore
+/// let hash = await!(async_runtime::spawn_blocking(move || hash_password(password)));
+/// ```
+///
+/// # Panics
+///
+/// A panic of `work` is resumed on the event loop in place of calling `callback`,
+/// it unwinds out of `run` or `block_on`. Work that is expected to panic can catch it
+/// with `std::panic::catch_unwind` and return the result.
+pub fn spawn_blocking<T, W, F>(work: W, callback: F)
+    where T: Send + 'static,
+          W: FnOnce() -> T + Send + 'static,
+          F: FnOnce(T) + 'static
+{
+    let completer = executor::pending(move |result: thread::Result<T>| {
+        match result {
+            Ok(value) => callback(value),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }).remote();
+
+    let job: Job = Box::new(move || completer.complete(panic::catch_unwind(AssertUnwindSafe(work))));
+    POOL.get_or_init(start).send(job).expect("the worker threads are gone");
+}
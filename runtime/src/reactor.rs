@@ -0,0 +1,304 @@
+//! Waits for file descriptors to become ready with epoll
+
+use executor;
+use libc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Token of the eventfd other threads wake the event loop with
+const NOTIFY_TOKEN: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Interest {
+    Readable,
+    Writable,
+}
+
+/// Callbacks waiting for a file descriptor
+#[derive(Default)]
+struct Waiters {
+    readable: Vec<Box<dyn FnOnce()>>,
+    writable: Vec<Box<dyn FnOnce()>>,
+    registered: bool,
+}
+
+pub(crate) struct Reactor {
+    epoll: RawFd,
+    notify: Arc<Notify>,
+    waiters: RefCell<HashMap<RawFd, Waiters>>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<Self> {
+        let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        let reactor = Reactor {
+            epoll,
+            notify: Arc::new(Notify::new()?),
+            waiters: RefCell::new(HashMap::new()),
+        };
+
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: NOTIFY_TOKEN };
+        cvt(unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, reactor.notify.fd, &mut event) })?;
+        Ok(reactor)
+    }
+
+    /// Wakes up the event loop from other threads
+    pub(crate) fn notify(&self) -> Arc<Notify> {
+        self.notify.clone()
+    }
+
+    /// Whether no callback waits for a file descriptor
+    pub(crate) fn is_idle(&self) -> bool {
+        self.waiters.borrow().is_empty()
+    }
+
+    /// Calls `callback` on the next turn after `fd` becomes ready.
+    /// The callback is dropped if epoll can't wait for the file descriptor.
+    pub(crate) fn wait(&self, fd: RawFd, interest: Interest, callback: Box<dyn FnOnce()>) -> io::Result<()> {
+        let mut waiters = self.waiters.borrow_mut();
+        let entry = waiters.entry(fd).or_default();
+        let callbacks = match interest {
+            Interest::Readable => &mut entry.readable,
+            Interest::Writable => &mut entry.writable,
+        };
+        callbacks.push(callback);
+
+        let result = self.update(fd, entry);
+        if result.is_err() {
+            match interest {
+                Interest::Readable => entry.readable.pop(),
+                Interest::Writable => entry.writable.pop(),
+            };
+            if !entry.registered {
+                waiters.remove(&fd);
+            }
+        }
+        result
+    }
+
+    /// Drops the callbacks waiting for a file descriptor that is about to be closed
+    pub(crate) fn deregister(&self, fd: RawFd) {
+        let entry = self.waiters.borrow_mut().remove(&fd);
+        if let Some(entry) = entry {
+            if entry.registered {
+                unsafe { libc::epoll_ctl(self.epoll, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) };
+            }
+        }
+    }
+
+    /// Waits for file descriptors to become ready, at most for `timeout` if it is set.
+    /// Returns the callbacks of those that are ready.
+    pub(crate) fn poll(&self, timeout: Option<Duration>) -> Vec<Box<dyn FnOnce()>> {
+        // Rounded up, waking up too early would only spin
+        let timeout = match timeout {
+            Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32,
+            None => -1,
+        };
+
+        let mut events: Vec<libc::epoll_event> = Vec::with_capacity(64);
+        let count = unsafe { libc::epoll_wait(self.epoll, events.as_mut_ptr(), 64, timeout) };
+        if count < 0 {
+            // Interrupted by a signal, the event loop calls again
+            return Vec::new();
+        }
+        unsafe { events.set_len(count as usize) };
+
+        let mut ready = Vec::new();
+        let mut waiters = self.waiters.borrow_mut();
+        for event in events {
+            let (token, flags) = (event.u64, event.events as i32);
+            if token == NOTIFY_TOKEN {
+                self.notify.clear();
+                continue;
+            }
+
+            let fd = token as RawFd;
+            let entry = match waiters.get_mut(&fd) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            // Errors and hang ups are reported by the operations that are retried
+            let failed = flags & (libc::EPOLLERR | libc::EPOLLHUP) != 0;
+            if failed || flags & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0 {
+                ready.append(&mut entry.readable);
+            }
+            if failed || flags & libc::EPOLLOUT != 0 {
+                ready.append(&mut entry.writable);
+            }
+            // Ignoring the error, the waiters that are left will be woken by a later event anyway
+            let _ = self.update(fd, entry);
+        }
+        waiters.retain(|_, entry| entry.registered);
+
+        ready
+    }
+
+    /// Registers the interests of the waiters with epoll
+    fn update(&self, fd: RawFd, entry: &mut Waiters) -> io::Result<()> {
+        let mut events = 0;
+        if !entry.readable.is_empty() {
+            events |= libc::EPOLLIN | libc::EPOLLRDHUP;
+        }
+        if !entry.writable.is_empty() {
+            events |= libc::EPOLLOUT;
+        }
+
+        if events == 0 {
+            if entry.registered {
+                entry.registered = false;
+                cvt(unsafe { libc::epoll_ctl(self.epoll, libc::EPOLL_CTL_DEL, fd, std::ptr::null_mut()) })?;
+            }
+            return Ok(());
+        }
+
+        let op = if entry.registered { libc::EPOLL_CTL_MOD } else { libc::EPOLL_CTL_ADD };
+        let mut event = libc::epoll_event { events: events as u32, u64: fd as u64 };
+        cvt(unsafe { libc::epoll_ctl(self.epoll, op, fd, &mut event) })?;
+        entry.registered = true;
+        Ok(())
+    }
+}
+
+impl Drop for Reactor {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.epoll) };
+    }
+}
+
+/// Eventfd that makes the event loop return from `epoll_wait`
+pub(crate) struct Notify {
+    fd: RawFd,
+}
+
+impl Notify {
+    fn new() -> io::Result<Self> {
+        let fd = cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+        Ok(Notify { fd })
+    }
+
+    pub(crate) fn wake(&self) {
+        let one: u64 = 1;
+        unsafe { libc::write(self.fd, &one as *const u64 as *const libc::c_void, 8) };
+    }
+
+    fn clear(&self) {
+        let mut count: u64 = 0;
+        unsafe { libc::read(self.fd, &mut count as *mut u64 as *mut libc::c_void, 8) };
+    }
+}
+
+impl Drop for Notify {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Non-blocking I/O object, its operations wait on the event loop until they don't block
+pub(crate) struct Source<T: AsRawFd> {
+    io: T,
+}
+
+impl<T: AsRawFd> Source<T> {
+    pub(crate) fn new(io: T) -> Rc<Self> {
+        Rc::new(Source { io })
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.io
+    }
+}
+
+impl<T: AsRawFd> Drop for Source<T> {
+    fn drop(&mut self) {
+        // The number can be reused by the next file descriptor that is opened
+        executor::deregister_fd(self.io.as_raw_fd());
+    }
+}
+
+/// Runs `op` until it doesn't block anymore, waiting for the file descriptor to become ready
+/// in between. `callback` receives the result on a later turn of the event loop.
+pub(crate) fn retry<T, R, O, F>(source: Rc<Source<T>>, interest: Interest, mut op: O, callback: F)
+    where T: AsRawFd + 'static,
+          R: 'static,
+          O: FnMut(&T) -> io::Result<R> + 'static,
+          F: FnOnce(io::Result<R>) + 'static
+{
+    match op(&source.io) {
+        Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+            let fd = source.io.as_raw_fd();
+            let pending = Rc::new(RefCell::new(Some((source, op, callback))));
+            let ready = pending.clone();
+            let waiting = executor::wait_fd(fd, interest, Box::new(move || {
+                if let Some((source, op, callback)) = ready.borrow_mut().take() {
+                    retry(source, interest, op, callback);
+                }
+            }));
+
+            if let Err(err) = waiting {
+                if let Some((_, _, callback)) = pending.borrow_mut().take() {
+                    executor::schedule(move || callback(Err(err)));
+                }
+            }
+        }
+        // Interrupted by a signal
+        Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {
+            executor::schedule(move || retry(source, interest, op, callback));
+        }
+        result => executor::schedule(move || callback(result)),
+    }
+}
+
+/// Reads up to `max` bytes, an empty buffer means the other side closed the stream
+pub(crate) fn read<T, F>(source: &Rc<Source<T>>, max: usize, callback: F)
+    where T: AsRawFd + 'static,
+          for<'a> &'a T: Read,
+          F: FnOnce(io::Result<Vec<u8>>) + 'static
+{
+    retry(source.clone(), Interest::Readable, move |mut io| {
+        let mut buf = vec![0; max];
+        let len = io.read(&mut buf)?;
+        buf.truncate(len);
+        Ok(buf)
+    }, callback);
+}
+
+/// Writes some of the data, returning how much
+pub(crate) fn write<T, F>(source: &Rc<Source<T>>, data: Vec<u8>, callback: F)
+    where T: AsRawFd + 'static,
+          for<'a> &'a T: Write,
+          F: FnOnce(io::Result<usize>) + 'static
+{
+    retry(source.clone(), Interest::Writable, move |mut io| io.write(&data), callback);
+}
+
+/// Writes all of the data, waiting whenever the buffer of the file descriptor is full
+pub(crate) fn write_all<T, F>(source: &Rc<Source<T>>, data: Vec<u8>, callback: F)
+    where T: AsRawFd + 'static,
+          for<'a> &'a T: Write,
+          F: FnOnce(io::Result<()>) + 'static
+{
+    let mut written = 0;
+    retry(source.clone(), Interest::Writable, move |mut io| {
+        while written < data.len() {
+            match io.write(&data[written..])? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                len => written += len,
+            }
+        }
+        Ok(())
+    }, callback);
+}
+
+/// Turns the result of a libc call into an error if it failed
+pub(crate) fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
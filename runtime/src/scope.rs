@@ -0,0 +1,185 @@
+//! Scopes that wait for every operation started in them
+
+use cancel::{CancellationToken, Cancelled};
+use executor::{self, Completer};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+struct State<T, E> {
+    /// Values of the children in the order they were spawned
+    values: Vec<Option<T>>,
+    running: usize,
+    /// The body is done spawning, the scope can finish once the children are done
+    closed: bool,
+    error: Option<E>,
+    /// Error of the first child that was cancelled, the scope fails with it if no child failed
+    cancelled: Option<E>,
+    token: CancellationToken,
+    callback: Option<Completer<Result<Vec<T>, E>>>,
+}
+
+/// Runs `body`, which spawns child operations into the scope, and completes once
+/// every child completed. The result holds the values of the children in the order
+/// they were spawned, or the error of the first child that failed.
+///
+/// A failing child cancels the other children. The ones spawned with `spawn_cancellable`
+/// are handed a token that stops them, the scope waits for them before it completes.
+///
+/// ```rust,ignore
+/// #[async]
+/// fn get_users(ids: Vec<u32>) -> io::Result<Vec<User>> {
+///     await!(async_runtime::scope(move |scope| {
+///         for id in ids {
+///             scope.spawn_cancellable(move |token, callback| get_user(token, id, callback));
+///         }
+///     }))
+/// }
+/// ```
+pub fn scope<T, E, B, F>(body: B, callback: F)
+    where T: 'static,
+          E: 'static,
+          B: FnOnce(&Scope<T, E>),
+          F: FnOnce(Result<Vec<T>, E>) + 'static
+{
+    let scope = Scope {
+        state: Rc::new(RefCell::new(State {
+            values: Vec::new(),
+            running: 0,
+            closed: false,
+            error: None,
+            cancelled: None,
+            token: CancellationToken::new(),
+            callback: Some(executor::pending(callback)),
+        })),
+    };
+
+    body(&scope);
+    scope.state.borrow_mut().closed = true;
+    scope.finish_if_done();
+}
+
+/// Handle to spawn children into a scope. Children can keep a clone to spawn more
+/// children, as long as the scope didn't complete.
+pub struct Scope<T, E> {
+    state: Rc<RefCell<State<T, E>>>,
+}
+
+impl<T: 'static, E: 'static> Scope<T, E> {
+    /// Starts a child operation. Once another child fails the scope stops waiting for it
+    /// and counts it as cancelled, its result is dropped when it arrives. The operation itself
+    /// keeps running, children that should stop their work are spawned with `spawn_cancellable`.
+    /// A child spawned after that isn't started.
+    ///
+    /// # Panics
+    ///
+    /// If the scope already completed
+    pub fn spawn<C>(&self, child: C)
+        where C: FnOnce(Box<dyn FnOnce(Result<T, E>)>),
+              E: From<Cancelled>
+    {
+        let (index, token) = self.start();
+        // The child is done with whichever comes first, its result or the cancellation
+        let done = Rc::new(Cell::new(false));
+        let (scope, cancelled) = (self.clone(), done.clone());
+        token.cancelled(move |()| {
+            if !cancelled.replace(true) {
+                scope.child_done(index, Err(E::from(Cancelled)), true);
+            }
+        });
+        if token.is_cancelled() {
+            return;
+        }
+
+        let scope = self.clone();
+        child(Box::new(move |result| {
+            // The token is kept until the child completes, dropping it forgets the cancellation callback
+            let _token = token;
+            if !done.replace(true) {
+                scope.child_done(index, result, false);
+            }
+        }));
+    }
+
+    /// Starts a child operation that stops once the token it is handed is cancelled,
+    /// like a function marked with `#[async(cancellable)]`. If a child is cancelled
+    /// without any child failing, the scope fails with `E::from(Cancelled)`.
+    ///
+    /// # Panics
+    ///
+    /// If the scope already completed
+    pub fn spawn_cancellable<C>(&self, child: C)
+        where C: FnOnce(CancellationToken, Box<dyn FnOnce(Result<Result<T, E>, Cancelled>)>),
+              E: From<Cancelled>
+    {
+        let (index, token) = self.start();
+        let scope = self.clone();
+        child(token, Box::new(move |result| {
+            match result {
+                Ok(result) => scope.child_done(index, result, false),
+                Err(cancelled) => scope.child_done(index, Err(E::from(cancelled)), true),
+            }
+        }));
+    }
+
+    /// Registers a child, returning its index and the token to cancel it with
+    fn start(&self) -> (usize, CancellationToken) {
+        let mut state = self.state.borrow_mut();
+        assert!(state.callback.is_some(), "can't spawn into a scope that already completed");
+        state.values.push(None);
+        state.running += 1;
+        (state.values.len() - 1, state.token.child_token())
+    }
+
+    /// Stores the result of a child, cancelling the others if it is the first to fail
+    fn child_done(&self, index: usize, result: Result<T, E>, cancelled: bool) {
+        let first_failure = {
+            let mut state = self.state.borrow_mut();
+            state.running -= 1;
+            match result {
+                Ok(value) => {
+                    state.values[index] = Some(value);
+                    false
+                }
+                Err(err) if cancelled => {
+                    state.cancelled.get_or_insert(err);
+                    false
+                }
+                Err(err) if state.error.is_none() => {
+                    state.error = Some(err);
+                    true
+                }
+                Err(_) => false,
+            }
+        };
+
+        // Cancelling can complete children right away, so the state isn't borrowed
+        if first_failure {
+            let token = self.state.borrow().token.clone();
+            token.cancel();
+        }
+        self.finish_if_done();
+    }
+
+    fn finish_if_done(&self) {
+        let mut state = self.state.borrow_mut();
+        if !state.closed || state.running > 0 {
+            return;
+        }
+        let callback = match state.callback.take() {
+            Some(callback) => callback,
+            None => return,
+        };
+
+        let result = match state.error.take().or_else(|| state.cancelled.take()) {
+            Some(err) => Err(err),
+            None => Ok(state.values.drain(..).map(|value| value.expect("every child completed")).collect()),
+        };
+        callback.complete(result);
+    }
+}
+
+impl<T, E> Clone for Scope<T, E> {
+    fn clone(&self) -> Self {
+        Scope { state: self.state.clone() }
+    }
+}
@@ -0,0 +1,368 @@
+use super::{AsyncStream, Next};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+/// Callback of an async operation started for a value of the stream
+pub type Then<U> = Box<dyn FnOnce(U)>;
+
+impl<T: 'static> AsyncStream<T> {
+    /// Stream of the values returned by `f`
+    pub fn map<U, F>(self, f: F) -> AsyncStream<U>
+        where U: 'static,
+              F: FnMut(T) -> U + 'static
+    {
+        let f = Rc::new(RefCell::new(f));
+        AsyncStream::pull(move |next| {
+            let f = f.clone();
+            self.next(move |value| next(value.map(|value| (f.borrow_mut())(value))));
+        })
+    }
+
+    /// Stream of the values `f` returns true for
+    pub fn filter<F: FnMut(&T) -> bool + 'static>(self, f: F) -> AsyncStream<T> {
+        let f = Rc::new(RefCell::new(f));
+        AsyncStream::pull(move |next| next_matching(self.clone(), f.clone(), next))
+    }
+
+    /// Stream of the results of an async function called with each value.
+    /// The next value is only requested once the function completed.
+    ///
+    /// ```rust,ignore
+    /// let users = ids.then(|id, callback| get_user(id, callback));
+    /// ```
+    pub fn then<U, F>(self, f: F) -> AsyncStream<U>
+        where U: 'static,
+              F: FnMut(T, Then<U>) + 'static
+    {
+        let f = Rc::new(RefCell::new(f));
+        AsyncStream::pull(move |next| {
+            let f = f.clone();
+            self.next(move |value| {
+                match value {
+                    Some(value) => (f.borrow_mut())(value, Box::new(move |result| next(Some(result)))),
+                    None => next(None),
+                }
+            });
+        })
+    }
+
+    /// Stream of the first `count` values, the rest is never requested
+    pub fn take(self, count: usize) -> AsyncStream<T> {
+        let remaining = Cell::new(count);
+        AsyncStream::pull(move |next| {
+            match remaining.get() {
+                0 => next(None),
+                count => {
+                    remaining.set(count - 1);
+                    self.next(next);
+                }
+            }
+        })
+    }
+
+    /// Stream of the values after the first `count`
+    pub fn skip(self, count: usize) -> AsyncStream<T> {
+        let remaining = Cell::new(count);
+        self.filter(move |_| {
+            match remaining.get() {
+                0 => true,
+                count => {
+                    remaining.set(count - 1);
+                    false
+                }
+            }
+        })
+    }
+
+    /// Stream of the values in groups of `size`, the last one can be smaller
+    ///
+    /// # Panics
+    ///
+    /// If `size` is 0
+    pub fn chunks(self, size: usize) -> AsyncStream<Vec<T>> {
+        assert!(size > 0, "chunks need to hold at least one value");
+        AsyncStream::pull(move |next| fill_chunk(self.clone(), Vec::with_capacity(size), size, next))
+    }
+
+    /// Like `then`, but runs the function for up to `limit` values at the same time.
+    /// Results are handed out in the order they complete.
+    ///
+    /// # Panics
+    ///
+    /// If `limit` is 0
+    pub fn buffer_unordered<U, F>(self, limit: usize, f: F) -> AsyncStream<U>
+        where U: 'static,
+              F: FnMut(T, Then<U>) + 'static
+    {
+        assert!(limit > 0, "at least one operation has to be able to run");
+        let buffer = Rc::new(Buffer {
+            source: self,
+            limit,
+            f: RefCell::new(f),
+            state: RefCell::new(BufferState {
+                running: 0,
+                pulling: false,
+                source_done: false,
+                ready: VecDeque::new(),
+                waiting: VecDeque::new(),
+            }),
+        });
+        AsyncStream::pull(move |next| {
+            buffer.state.borrow_mut().waiting.push_back(next);
+            Buffer::dispatch(&buffer);
+            Buffer::fill(&buffer);
+        })
+    }
+
+    /// Stream of the values of both streams, in the order they arrive.
+    /// It ends once both of them ended.
+    pub fn merge(self, other: AsyncStream<T>) -> AsyncStream<T> {
+        let merge = Rc::new(Merge {
+            sources: vec![self, other],
+            state: RefCell::new(MergeState {
+                pulling: vec![false; 2],
+                done: vec![false; 2],
+                ready: VecDeque::new(),
+                waiting: VecDeque::new(),
+            }),
+        });
+        AsyncStream::pull(move |next| {
+            merge.state.borrow_mut().waiting.push_back(next);
+            Merge::dispatch(&merge);
+            Merge::pull(&merge);
+        })
+    }
+
+    /// Stream of pairs of the values of both streams, requested at the same time.
+    /// It ends with the shorter one.
+    pub fn zip<U: 'static>(self, other: AsyncStream<U>) -> AsyncStream<(T, U)> {
+        AsyncStream::pull(move |next| {
+            let pair = Rc::new(RefCell::new((None, None, Some(next))));
+            let left = pair.clone();
+            self.next(move |value| {
+                left.borrow_mut().0 = Some(value);
+                complete_pair(&left);
+            });
+            let right = pair;
+            other.next(move |value| {
+                right.borrow_mut().1 = Some(value);
+                complete_pair(&right);
+            });
+        })
+    }
+
+    /// Completes with the value `f` folded all values of the stream into, starting with `init`
+    ///
+    /// ```rust,ignore
+    /// let total = await!(prices.fold(0, |total, price| total + price));
+    /// ```
+    pub fn fold<A, F, C>(self, init: A, f: F, callback: C)
+        where A: 'static,
+              F: FnMut(A, T) -> A + 'static,
+              C: FnOnce(A) + 'static
+    {
+        fold_next(self, init, f, callback);
+    }
+}
+
+fn next_matching<T, F>(stream: AsyncStream<T>, f: Rc<RefCell<F>>, next: Next<T>)
+    where T: 'static,
+          F: FnMut(&T) -> bool + 'static
+{
+    stream.clone().next(move |value| {
+        match value {
+            Some(ref value) if !(f.borrow_mut())(value) => next_matching(stream, f, next),
+            value => next(value),
+        }
+    });
+}
+
+fn fill_chunk<T: 'static>(stream: AsyncStream<T>, mut chunk: Vec<T>, size: usize, next: Next<Vec<T>>) {
+    stream.clone().next(move |value| {
+        match value {
+            Some(value) => {
+                chunk.push(value);
+                if chunk.len() == size {
+                    next(Some(chunk));
+                } else {
+                    fill_chunk(stream, chunk, size, next);
+                }
+            }
+            None if chunk.is_empty() => next(None),
+            None => next(Some(chunk)),
+        }
+    });
+}
+
+type Pair<T, U> = (Option<Option<T>>, Option<Option<U>>, Option<Next<(T, U)>>);
+
+/// Hands out the pair once both values arrived
+fn complete_pair<T, U>(pair: &Rc<RefCell<Pair<T, U>>>) {
+    let (left, right, next) = {
+        let mut pair = pair.borrow_mut();
+        if pair.0.is_none() || pair.1.is_none() {
+            return;
+        }
+        (pair.0.take().unwrap(), pair.1.take().unwrap(), pair.2.take().unwrap())
+    };
+    match (left, right) {
+        (Some(left), Some(right)) => next(Some((left, right))),
+        _ => next(None),
+    }
+}
+
+fn fold_next<T, A, F, C>(stream: AsyncStream<T>, acc: A, mut f: F, callback: C)
+    where T: 'static,
+          A: 'static,
+          F: FnMut(A, T) -> A + 'static,
+          C: FnOnce(A) + 'static
+{
+    stream.clone().next(move |value| {
+        match value {
+            Some(value) => {
+                let acc = f(acc, value);
+                fold_next(stream, acc, f, callback);
+            }
+            None => callback(acc),
+        }
+    });
+}
+
+struct Buffer<T, U, F> {
+    source: AsyncStream<T>,
+    limit: usize,
+    f: RefCell<F>,
+    state: RefCell<BufferState<U>>,
+}
+
+struct BufferState<U> {
+    /// Operations that haven't completed yet
+    running: usize,
+    /// Whether the next value of the source was requested
+    pulling: bool,
+    source_done: bool,
+    ready: VecDeque<U>,
+    waiting: VecDeque<Next<U>>,
+}
+
+impl<T, U, F> Buffer<T, U, F>
+    where T: 'static,
+          U: 'static,
+          F: FnMut(T, Then<U>) + 'static
+{
+    /// Starts the operation for the next value, unless `limit` results are running or unclaimed
+    fn fill(buffer: &Rc<Self>) {
+        {
+            let mut state = buffer.state.borrow_mut();
+            if state.pulling || state.source_done || state.running + state.ready.len() >= buffer.limit {
+                return;
+            }
+            state.pulling = true;
+        }
+
+        let this = buffer.clone();
+        buffer.source.next(move |value| {
+            this.state.borrow_mut().pulling = false;
+            match value {
+                Some(value) => {
+                    this.state.borrow_mut().running += 1;
+                    let done = this.clone();
+                    (this.f.borrow_mut())(value, Box::new(move |result| {
+                        {
+                            let mut state = done.state.borrow_mut();
+                            state.running -= 1;
+                            state.ready.push_back(result);
+                        }
+                        Buffer::dispatch(&done);
+                        Buffer::fill(&done);
+                    }));
+                    Buffer::fill(&this);
+                }
+                None => {
+                    this.state.borrow_mut().source_done = true;
+                    Buffer::dispatch(&this);
+                }
+            }
+        });
+    }
+
+    /// Hands results to the waiting consumers, or the end once nothing is left
+    fn dispatch(buffer: &Rc<Self>) {
+        let mut handed = Vec::new();
+        {
+            let mut state = buffer.state.borrow_mut();
+            while !state.waiting.is_empty() && !state.ready.is_empty() {
+                let next = state.waiting.pop_front().unwrap();
+                handed.push((next, state.ready.pop_front()));
+            }
+            if state.source_done && state.running == 0 && state.ready.is_empty() {
+                handed.extend(mem::take(&mut state.waiting).into_iter().map(|next| (next, None)));
+            }
+        }
+        for (next, value) in handed {
+            next(value);
+        }
+    }
+}
+
+struct Merge<T> {
+    sources: Vec<AsyncStream<T>>,
+    state: RefCell<MergeState<T>>,
+}
+
+struct MergeState<T> {
+    pulling: Vec<bool>,
+    done: Vec<bool>,
+    ready: VecDeque<T>,
+    waiting: VecDeque<Next<T>>,
+}
+
+impl<T: 'static> Merge<T> {
+    /// Requests the next value of every source that isn't ended or requested already,
+    /// as long as somebody waits for one
+    fn pull(merge: &Rc<Self>) {
+        for (i, source) in merge.sources.iter().enumerate() {
+            {
+                let mut state = merge.state.borrow_mut();
+                if state.waiting.len() <= state.ready.len() || state.pulling[i] || state.done[i] {
+                    continue;
+                }
+                state.pulling[i] = true;
+            }
+
+            let this = merge.clone();
+            source.next(move |value| {
+                {
+                    let mut state = this.state.borrow_mut();
+                    state.pulling[i] = false;
+                    match value {
+                        Some(value) => state.ready.push_back(value),
+                        None => state.done[i] = true,
+                    }
+                }
+                Merge::dispatch(&this);
+                Merge::pull(&this);
+            });
+        }
+    }
+
+    /// Hands values to the waiting consumers, or the end once every source ended
+    fn dispatch(merge: &Rc<Self>) {
+        let mut handed = Vec::new();
+        {
+            let mut state = merge.state.borrow_mut();
+            while !state.waiting.is_empty() && !state.ready.is_empty() {
+                let next = state.waiting.pop_front().unwrap();
+                handed.push((next, state.ready.pop_front()));
+            }
+            if state.done.iter().all(|&done| done) && state.ready.is_empty() {
+                handed.extend(mem::take(&mut state.waiting).into_iter().map(|next| (next, None)));
+            }
+        }
+        for (next, value) in handed {
+            next(value);
+        }
+    }
+}
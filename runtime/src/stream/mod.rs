@@ -0,0 +1,199 @@
+//! Streams of values produced asynchronously, pulled one at a time.
+//! Functions marked with `#[async_stream]` return one.
+//!
+//! ```rust,ignore
+//! #[async_stream]
+//! fn pages(client: Client) -> Page {
+//!     let mut page = await!(client.clone().first_page());
+//!     while let Some(next) = page.next.clone() {
+//!         r#yield!(page);
+//!         page = await!(client.clone().get_page(next));
+//!     }
+//!     r#yield!(page);
+//! }
+//!
+//! #[async]
+//! fn print_pages(client: Client) {
+//!     let pages = pages(client);
+//!     while let Some(page) = await!(pages.clone().next()) {
+//!         println!("{:?}", page);
+//!     }
+//! }
+//! ```
+//!
+//! Combinators like `map`, `filter`, `then` and `merge` build new streams out of existing ones,
+//! requesting the values of the streams they consume as their own values are requested.
+//! `fold` completes with all values of a stream folded into one.
+//!
+//! ```rust,ignore
+//! let sizes = pages(client).then(|page, callback| download(page, callback)).map(|file| file.len());
+//! let total = await!(sizes.fold(0, |total, size| total + size));
+//! ```
+
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+pub use self::combinators::Then;
+
+mod combinators;
+
+/// Continues a stream, handing its next step to the callback
+pub type Resume<T> = Box<dyn FnOnce(Emit<T>)>;
+
+/// Callback receiving the next step of a stream
+pub type Emit<T> = Box<dyn FnOnce(Step<T>)>;
+
+/// Callback receiving the next value of a stream, `None` once it ended
+type Next<T> = Box<dyn FnOnce(Option<T>)>;
+
+/// Step of a stream, the next value with how to continue after it, or its end
+pub enum Step<T> {
+    Yield(T, Resume<T>),
+    Done,
+}
+
+struct State<T> {
+    /// `None` while the stream produces a value and once it ended
+    resume: Option<Resume<T>>,
+    done: bool,
+    waiting: VecDeque<Completer<Option<T>>>,
+}
+
+/// A stream that doesn't do anything until its next value is requested.
+/// Cloning it creates another handle to the same stream, each value is only received once.
+pub struct AsyncStream<T> {
+    state: Rc<RefCell<State<T>>>,
+}
+
+impl<T> Clone for AsyncStream<T> {
+    fn clone(&self) -> AsyncStream<T> {
+        AsyncStream { state: self.state.clone() }
+    }
+}
+
+impl<T: 'static> AsyncStream<T> {
+    /// Stream that runs `start` once its first value is requested.
+    /// `start` hands the first step to the callback it receives.
+    pub fn new<F: FnOnce(Emit<T>) + 'static>(start: F) -> AsyncStream<T> {
+        let state = State { resume: Some(Box::new(start)), done: false, waiting: VecDeque::new() };
+        AsyncStream { state: Rc::new(RefCell::new(state)) }
+    }
+
+    /// Stream without any values
+    pub fn empty() -> AsyncStream<T> {
+        AsyncStream::new(|emit: Emit<T>| emit(Step::Done))
+    }
+
+    /// Stream of the values of an iterator
+    pub fn iter<I>(values: I) -> AsyncStream<T>
+        where I: IntoIterator<Item = T>,
+              I::IntoIter: 'static
+    {
+        AsyncStream::new(resume_iter(values.into_iter()))
+    }
+
+    /// Completes with the next value, or `None` once the stream ended.
+    /// Values are handed out in the order they were asked for. The callback runs on a later
+    /// turn of the event loop, so consuming a long stream that never waits doesn't grow the stack.
+    pub fn next<F: FnOnce(Option<T>) + 'static>(&self, callback: F) {
+        let next = executor::pending(callback);
+        let resume = {
+            let mut state = self.state.borrow_mut();
+            if state.done {
+                drop(state);
+                next.complete(None);
+                return;
+            }
+            state.waiting.push_back(next);
+            state.resume.take()
+        };
+        // Otherwise the stream is producing a value already, the next one goes to this waiter
+        if let Some(resume) = resume {
+            self.resume(resume);
+        }
+    }
+
+    /// Whether the stream ended
+    pub fn is_done(&self) -> bool {
+        self.state.borrow().done
+    }
+
+    /// Stream that calls `next` whenever a value is requested, until it hands over `None`
+    fn pull<F: Fn(Next<T>) + 'static>(next: F) -> AsyncStream<T> {
+        AsyncStream::new(resume_pull(Rc::new(next)))
+    }
+
+    fn resume(&self, resume: Resume<T>) {
+        let stream = self.clone();
+        resume(Box::new(move |step| stream.step(step)));
+    }
+
+    fn step(&self, step: Step<T>) {
+        let mut state = self.state.borrow_mut();
+        let waiter = state.waiting.pop_front().expect("stream continued without being asked for a value");
+        match step {
+            Step::Yield(value, resume) => {
+                if state.waiting.is_empty() {
+                    state.resume = Some(resume);
+                    drop(state);
+                    waiter.complete(Some(value));
+                } else {
+                    drop(state);
+                    waiter.complete(Some(value));
+                    self.resume(resume);
+                }
+            }
+            Step::Done => {
+                state.done = true;
+                let waiting = mem::take(&mut state.waiting);
+                drop(state);
+                waiter.complete(None);
+                for waiter in waiting {
+                    waiter.complete(None);
+                }
+            }
+        }
+    }
+}
+
+fn resume_iter<T, I>(mut values: I) -> impl FnOnce(Emit<T>)
+    where T: 'static,
+          I: Iterator<Item = T> + 'static
+{
+    move |emit: Emit<T>| {
+        match values.next() {
+            Some(value) => emit(Step::Yield(value, Box::new(resume_iter(values)))),
+            None => emit(Step::Done),
+        }
+    }
+}
+
+fn resume_pull<T: 'static>(next: Rc<dyn Fn(Next<T>)>) -> impl FnOnce(Emit<T>) {
+    move |emit: Emit<T>| {
+        let resume = next.clone();
+        next(Box::new(move |value| {
+            match value {
+                Some(value) => emit(Step::Yield(value, Box::new(resume_pull(resume)))),
+                None => emit(Step::Done),
+            }
+        }));
+    }
+}
+
+/// Hands a value of a `#[async_stream]` function to its consumer.
+/// The function continues with `resume` once the next value is requested.
+/// The value comes first so it's evaluated before the callback is moved, it may return with `?`.
+pub fn yield_item<T, F>(value: T, emit: Emit<T>, resume: F)
+    where T: 'static,
+          F: FnOnce(Emit<T>) + 'static
+{
+    emit(Step::Yield(value, Box::new(resume)));
+}
+
+/// Hands the last value of a `#[async_stream]` function to its consumer, ending the stream
+pub fn yield_last<T: 'static>(value: T, emit: Emit<T>) {
+    emit(Step::Yield(value, Box::new(|emit: Emit<T>| emit(Step::Done))));
+}
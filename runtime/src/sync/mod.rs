@@ -0,0 +1,32 @@
+//! Locks and signals for async functions running on the same event loop.
+//!
+//! Guards of the std locks can't be held across `await!`, the continuation that
+//! waits for the lock would block the event loop the holder needs to get to the point
+//! where it unlocks. The locks here complete their callback once the lock is free instead,
+//! and their guards can be held across await points:
+//!
+//! ```rust,ignore
+//! #[async]
+//! fn increment(counter: Mutex<u32>) {
+//!     let mut count = await!(counter.lock());
+//!     await!(async_runtime::sleep(Duration::from_millis(10)));
+//!     *count += 1;
+//! }
+//! ```
+//!
+//! Waiters are served in the order they started waiting, a waiter that needs more
+//! than what is available holds up the ones behind it.
+//!
+//! The locks aren't poisoned. A panic unwinds out of the event loop, which drops the
+//! guard and releases the lock, so the value is only visible half updated if the lock is used
+//! again on another event loop.
+
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::notify::Notify;
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::{Permit, Semaphore};
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
@@ -0,0 +1,60 @@
+use super::{Permit, Semaphore};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+struct Inner<T> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+/// Gives one continuation at a time access to the value.
+/// Cloning it creates another handle to the same value.
+pub struct Mutex<T> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T: 'static> Mutex<T> {
+    pub fn new(value: T) -> Mutex<T> {
+        Mutex { inner: Rc::new(Inner { semaphore: Semaphore::new(1), value: UnsafeCell::new(value) }) }
+    }
+
+    /// Completes with the guard once the mutex is unlocked
+    pub fn lock<F: FnOnce(MutexGuard<T>) + 'static>(&self, callback: F) {
+        let inner = self.inner.clone();
+        self.inner.semaphore.acquire(move |permit| callback(MutexGuard { inner, _permit: permit }));
+    }
+
+    /// Locks the mutex if it is unlocked and nobody waits for it
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let permit = self.inner.semaphore.try_acquire()?;
+        Some(MutexGuard { inner: self.inner.clone(), _permit: permit })
+    }
+}
+
+impl<T> Clone for Mutex<T> {
+    fn clone(&self) -> Self {
+        Mutex { inner: self.inner.clone() }
+    }
+}
+
+/// Access to the value of a mutex, unlocking it when dropped
+pub struct MutexGuard<T> {
+    inner: Rc<Inner<T>>,
+    _permit: Permit,
+}
+
+impl<T> Deref for MutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // The permit is the only one of the semaphore, no other guard exists
+        unsafe { &*self.inner.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.value.get() }
+    }
+}
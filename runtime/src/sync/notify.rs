@@ -0,0 +1,59 @@
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+struct State {
+    waiting: VecDeque<Completer<()>>,
+    /// Set by `notify_one` while nobody waits, the next waiter doesn't have to wait then
+    permit: bool,
+}
+
+/// Wakes up continuations waiting for something to happen.
+/// Cloning it creates another handle that notifies the same waiters.
+#[derive(Clone)]
+pub struct Notify {
+    state: Rc<RefCell<State>>,
+}
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify { state: Rc::new(RefCell::new(State { waiting: VecDeque::new(), permit: false })) }
+    }
+
+    /// Completes once notified
+    pub fn notified<F: FnOnce(()) + 'static>(&self, callback: F) {
+        let waiting = executor::pending(callback);
+        let mut state = self.state.borrow_mut();
+        if mem::replace(&mut state.permit, false) {
+            waiting.complete(());
+        } else {
+            state.waiting.push_back(waiting);
+        }
+    }
+
+    /// Wakes the waiter that waits the longest. If nobody waits, the next one to call
+    /// `notified` doesn't have to wait, notifying more often before that makes no difference.
+    pub fn notify_one(&self) {
+        let mut state = self.state.borrow_mut();
+        match state.waiting.pop_front() {
+            Some(waiting) => waiting.complete(()),
+            None => state.permit = true,
+        }
+    }
+
+    /// Wakes every waiter, without affecting those that start waiting later
+    pub fn notify_waiters(&self) {
+        let waiting = mem::take(&mut self.state.borrow_mut().waiting);
+        for waiting in waiting {
+            waiting.complete(());
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
@@ -0,0 +1,89 @@
+use super::{Permit, Semaphore};
+use std::cell::UnsafeCell;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+/// Readers take one permit each, a writer takes all of them
+const MAX_READERS: usize = usize::MAX >> 3;
+
+struct Inner<T> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+/// Gives access to the value to many readers or to one writer.
+/// A waiting writer keeps readers that come after it from getting in before it,
+/// so writers aren't starved. Cloning it creates another handle to the same value.
+pub struct RwLock<T> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T: 'static> RwLock<T> {
+    pub fn new(value: T) -> RwLock<T> {
+        RwLock { inner: Rc::new(Inner { semaphore: Semaphore::new(MAX_READERS), value: UnsafeCell::new(value) }) }
+    }
+
+    /// Completes with shared access once no writer holds or waits before it
+    pub fn read<F: FnOnce(RwLockReadGuard<T>) + 'static>(&self, callback: F) {
+        let inner = self.inner.clone();
+        self.inner.semaphore.acquire(move |permit| callback(RwLockReadGuard { inner, _permit: permit }));
+    }
+
+    /// Completes with exclusive access once every earlier reader and writer is done
+    pub fn write<F: FnOnce(RwLockWriteGuard<T>) + 'static>(&self, callback: F) {
+        let inner = self.inner.clone();
+        self.inner.semaphore.acquire_many(MAX_READERS, move |permit| callback(RwLockWriteGuard { inner, _permit: permit }));
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let permit = self.inner.semaphore.try_acquire()?;
+        Some(RwLockReadGuard { inner: self.inner.clone(), _permit: permit })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        let permit = self.inner.semaphore.try_acquire_many(MAX_READERS)?;
+        Some(RwLockWriteGuard { inner: self.inner.clone(), _permit: permit })
+    }
+}
+
+impl<T> Clone for RwLock<T> {
+    fn clone(&self) -> Self {
+        RwLock { inner: self.inner.clone() }
+    }
+}
+
+/// Shared access to the value of a lock
+pub struct RwLockReadGuard<T> {
+    inner: Rc<Inner<T>>,
+    _permit: Permit,
+}
+
+impl<T> Deref for RwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // No writer holds every permit while a reader holds one
+        unsafe { &*self.inner.value.get() }
+    }
+}
+
+/// Exclusive access to the value of a lock
+pub struct RwLockWriteGuard<T> {
+    inner: Rc<Inner<T>>,
+    _permit: Permit,
+}
+
+impl<T> Deref for RwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Holding every permit, no other guard exists
+        unsafe { &*self.inner.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.value.get() }
+    }
+}
@@ -0,0 +1,109 @@
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+struct State {
+    permits: usize,
+    /// Number of permits each waiter needs
+    waiting: VecDeque<(usize, Completer<Permit>)>,
+}
+
+/// Hands out a limited number of permits, they are given back when dropped.
+/// Cloning it creates another handle to the same permits.
+#[derive(Clone)]
+pub struct Semaphore {
+    state: Rc<RefCell<State>>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore { state: Rc::new(RefCell::new(State { permits, waiting: VecDeque::new() })) }
+    }
+
+    /// Completes with a permit once one is available
+    pub fn acquire<F: FnOnce(Permit) + 'static>(&self, callback: F) {
+        self.acquire_many(1, callback);
+    }
+
+    /// Completes once `count` permits are available at the same time
+    pub fn acquire_many<F: FnOnce(Permit) + 'static>(&self, count: usize, callback: F) {
+        self.state.borrow_mut().waiting.push_back((count, executor::pending(callback)));
+        self.dispatch();
+    }
+
+    /// Takes a permit without waiting, if one is available and nobody waits before
+    pub fn try_acquire(&self) -> Option<Permit> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, count: usize) -> Option<Permit> {
+        let mut state = self.state.borrow_mut();
+        if !state.waiting.is_empty() || state.permits < count {
+            return None;
+        }
+        state.permits -= count;
+        Some(Permit { semaphore: self.clone(), count })
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.borrow().permits
+    }
+
+    /// Adds permits, waking the waiters they are enough for
+    pub fn add_permits(&self, count: usize) {
+        self.state.borrow_mut().permits += count;
+        self.dispatch();
+    }
+
+    /// Hands permits to the waiters at the front of the queue
+    fn dispatch(&self) {
+        let mut granted = Vec::new();
+        {
+            let mut state = self.state.borrow_mut();
+            while state.waiting.front().is_some_and(|&(count, _)| count <= state.permits) {
+                let (count, waiting) = state.waiting.pop_front().unwrap();
+                state.permits -= count;
+                granted.push((Permit { semaphore: self.clone(), count }, waiting));
+            }
+        }
+        for (permit, waiting) in granted {
+            waiting.complete(permit);
+        }
+    }
+}
+
+/// Permits taken from a semaphore, given back when it is dropped
+pub struct Permit {
+    semaphore: Semaphore,
+    count: usize,
+}
+
+impl Permit {
+    /// Keeps the permits from being given back
+    pub fn forget(mut self) {
+        self.count = 0;
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let count = mem::replace(&mut self.count, 0);
+        if count == 0 {
+            return;
+        }
+        // A guard held by a pending operation is dropped after the event loop is gone when
+        // a panic unwinds out of it. The waiters of that loop can't be woken anymore, they are dropped.
+        if executor::is_running() {
+            self.semaphore.add_permits(count);
+        } else {
+            let waiting = {
+                let mut state = self.semaphore.state.borrow_mut();
+                state.permits += count;
+                mem::take(&mut state.waiting)
+            };
+            drop(waiting);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Tasks, async functions the event loop runs independently of their caller
+
+use cancel::{CancellationToken, Cancelled};
+use executor::{self, Completer};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+
+struct State<T> {
+    /// Result of the function until it's joined
+    result: Option<T>,
+    finished: bool,
+    aborted: bool,
+    joining: Option<Completer<Result<T, JoinError>>>,
+    /// Cancelled when the task is aborted
+    token: CancellationToken,
+}
+
+/// Runs an async function on the event loop, starting it on the next turn.
+/// The returned handle can be awaited for its result, dropping it lets the task run on its own.
+///
+/// ```rust,ignore
+/// let handle = async_runtime::spawn(|callback| get_user(1, callback));
+/// ..
+/// let user = await!(handle.join())?;
+/// ```
+///
+/// Functions without a return type don't tell when they finish,
+/// they need an explicit `-> ()` to be spawned.
+pub fn spawn<T, F>(f: F) -> JoinHandle<T>
+    where T: 'static,
+          F: FnOnce(Box<dyn FnOnce(T)>) + 'static
+{
+    spawn_cancellable(move |_, callback| f(Box::new(move |value| callback(Ok(value)))))
+}
+
+/// Runs an async function marked with `#[async(cancellable)]` as a task. The token it is handed
+/// is cancelled when the task is aborted, so it stops once it continues after an await.
+///
+/// ```rust,ignore
+/// let handle = async_runtime::spawn_cancellable(|token, callback| poll_updates(token, callback));
+/// ..
+/// handle.abort();
+/// ```
+pub fn spawn_cancellable<T, F>(f: F) -> JoinHandle<T>
+    where T: 'static,
+          F: FnOnce(CancellationToken, Box<dyn FnOnce(Result<T, Cancelled>)>) + 'static
+{
+    let state = Rc::new(RefCell::new(State {
+        result: None,
+        finished: false,
+        aborted: false,
+        joining: None,
+        token: CancellationToken::new(),
+    }));
+
+    let task = state.clone();
+    executor::schedule(move || {
+        if task.borrow().aborted {
+            task.borrow_mut().finished = true;
+            return;
+        }
+        let token = task.borrow().token.clone();
+        f(token, Box::new(move |result| {
+            let mut state = task.borrow_mut();
+            state.finished = true;
+            if state.aborted {
+                return;
+            }
+            match result {
+                Ok(value) => {
+                    match state.joining.take() {
+                        Some(joining) => joining.complete(Ok(value)),
+                        None => state.result = Some(value),
+                    }
+                }
+                // The function cancelled its token itself, which ends the task like aborting it
+                Err(Cancelled) => {
+                    state.aborted = true;
+                    if let Some(joining) = state.joining.take() {
+                        joining.complete(Err(JoinError));
+                    }
+                }
+            }
+        }));
+    });
+
+    JoinHandle { state }
+}
+
+/// The task was aborted before it completed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JoinError;
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("task was aborted")
+    }
+}
+
+impl Error for JoinError {}
+
+/// Handle of a spawned task
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<State<T>>>,
+}
+
+impl<T: 'static> JoinHandle<T> {
+    /// Completes with the result of the task, or an error if it was aborted
+    pub fn join<F: FnOnce(Result<T, JoinError>) + 'static>(self, callback: F) {
+        let joining = executor::pending(callback);
+        let mut state = self.state.borrow_mut();
+        if state.aborted {
+            joining.complete(Err(JoinError));
+        } else if let Some(result) = state.result.take() {
+            joining.complete(Ok(result));
+        } else {
+            state.joining = Some(joining);
+        }
+    }
+
+    /// Whether the function completed, or won't run anymore because it was aborted before it started
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+
+    /// Aborts the task, see `AbortHandle::abort`
+    pub fn abort(&self) {
+        abort(&self.state);
+    }
+
+    /// A handle that aborts the task, to keep after the join handle was awaited
+    pub fn abort_handle(&self) -> AbortHandle {
+        let state = self.state.clone();
+        AbortHandle { abort: Rc::new(move || abort(&state)) }
+    }
+}
+
+fn abort<T: 'static>(state: &RefCell<State<T>>) {
+    let token = {
+        let mut state = state.borrow_mut();
+        if state.finished {
+            return;
+        }
+        state.aborted = true;
+        if let Some(joining) = state.joining.take() {
+            joining.complete(Err(JoinError));
+        }
+        state.token.clone()
+    };
+    token.cancel();
+}
+
+/// Aborts a task without owning its result
+#[derive(Clone)]
+pub struct AbortHandle {
+    abort: Rc<dyn Fn()>,
+}
+
+impl AbortHandle {
+    /// A task that didn't start yet never runs. One started with `spawn_cancellable` has its token
+    /// cancelled and stops at its next await. Continuations of other tasks still run, but their
+    /// result is dropped. Either way joining it fails. Aborting a finished task does nothing.
+    pub fn abort(&self) {
+        (self.abort)();
+    }
+}
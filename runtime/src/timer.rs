@@ -0,0 +1,208 @@
+//! Timers of the event loop, kept in a hashed timer wheel
+
+use executor;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Number of slots of the wheel, timers further away wait for later rounds
+const SLOTS: u64 = 512;
+/// Resolution of the wheel in milliseconds
+const TICK_MILLIS: u64 = 1;
+
+/// Timers sorted into slots by the tick they are due at.
+/// Each slot holds the timers of every round, so firing a slot only takes out those that are due.
+pub(crate) struct Wheel {
+    origin: Instant,
+    /// Every timer up to this tick has fired
+    elapsed: u64,
+    slots: Vec<Vec<Timer>>,
+    /// Tick of every pending timer, to find its slot when it's cancelled
+    ticks: HashMap<usize, u64>,
+}
+
+struct Timer {
+    id: usize,
+    tick: u64,
+    callback: Box<dyn FnOnce()>,
+}
+
+impl Wheel {
+    pub(crate) fn new(origin: Instant) -> Self {
+        Wheel {
+            origin,
+            elapsed: 0,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            ticks: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ticks.is_empty()
+    }
+
+    /// Adds a timer that fires once `deadline` has passed
+    pub(crate) fn insert(&mut self, id: usize, deadline: Instant, callback: Box<dyn FnOnce()>) {
+        // Rounded up, timers never fire early
+        let nanos = deadline.saturating_duration_since(self.origin).as_nanos();
+        let tick_nanos = u128::from(TICK_MILLIS) * 1_000_000;
+        let tick = nanos.div_ceil(tick_nanos) as u64;
+        let tick = tick.max(self.elapsed + 1);
+
+        self.ticks.insert(id, tick);
+        self.slots[(tick % SLOTS) as usize].push(Timer { id, tick, callback });
+    }
+
+    /// Removes a timer that didn't fire yet
+    pub(crate) fn cancel(&mut self, id: usize) {
+        if let Some(tick) = self.ticks.remove(&id) {
+            self.slots[(tick % SLOTS) as usize].retain(|timer| timer.id != id);
+        }
+    }
+
+    /// Takes out the callbacks of every timer that is due at `now`, earliest first
+    pub(crate) fn advance(&mut self, now: Instant) -> Vec<Box<dyn FnOnce()>> {
+        let now_tick = self.tick_of(now);
+        if now_tick <= self.elapsed {
+            return Vec::new();
+        }
+
+        // Every slot is visited at most once, even if many rounds passed
+        let mut fired = Vec::new();
+        for tick in self.elapsed + 1..=self.elapsed + (now_tick - self.elapsed).min(SLOTS) {
+            let slot = &mut self.slots[(tick % SLOTS) as usize];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= now_tick {
+                    fired.push(slot.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.elapsed = now_tick;
+
+        fired.sort_by_key(|timer| (timer.tick, timer.id));
+        fired.into_iter()
+             .map(|timer| {
+                 self.ticks.remove(&timer.id);
+                 timer.callback
+             })
+             .collect()
+    }
+
+    /// When the next timer is due
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        // The first slot with a timer of the current round holds the earliest one
+        for tick in self.elapsed + 1..=self.elapsed + SLOTS {
+            if self.slots[(tick % SLOTS) as usize].iter().any(|timer| timer.tick == tick) {
+                return Some(self.instant_of(tick));
+            }
+        }
+        // Otherwise every timer is at least a round away
+        self.ticks.values().min().map(|&tick| self.instant_of(tick))
+    }
+
+    fn tick_of(&self, instant: Instant) -> u64 {
+        instant.saturating_duration_since(self.origin).as_millis() as u64 / TICK_MILLIS
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.origin + Duration::from_millis(tick * TICK_MILLIS)
+    }
+}
+
+/// Completes once `duration` has passed
+///
+/// ```rust,ignore
+/// await!(async_runtime::sleep(Duration::from_millis(100)));
+/// ```
+pub fn sleep<F: FnOnce(()) + 'static>(duration: Duration, callback: F) {
+    sleep_until(Instant::now() + duration, callback);
+}
+
+/// Completes once `deadline` has passed
+pub fn sleep_until<F: FnOnce(()) + 'static>(deadline: Instant, callback: F) {
+    executor::add_timer(deadline, Box::new(move || callback(())));
+}
+
+/// The operation passed to `timeout` didn't complete in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("operation timed out")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// Runs a callback style operation, completing with `Err(Elapsed)` if it takes longer than `duration`.
+/// A result that arrives after the deadline is ignored.
+///
+/// ```rust,ignore
+/// match await!(async_runtime::timeout(Duration::from_secs(1), |callback| get_user(1, callback))) {
+///     Ok(user) => ..,
+///     Err(Elapsed) => ..,
+/// }
+/// ```
+pub fn timeout<T, O, F>(duration: Duration, operation: O, callback: F)
+    where T: 'static,
+          O: FnOnce(Box<dyn FnOnce(T)>),
+          F: FnOnce(Result<T, Elapsed>) + 'static
+{
+    let callback = Rc::new(RefCell::new(Some(callback)));
+
+    let expired = callback.clone();
+    let timer = executor::add_timer(Instant::now() + duration, Box::new(move || {
+        let callback = expired.borrow_mut().take();
+        if let Some(callback) = callback {
+            callback(Err(Elapsed));
+        }
+    }));
+
+    operation(Box::new(move |value| {
+        let callback = callback.borrow_mut().take();
+        if let Some(callback) = callback {
+            // The event loop doesn't have to wait for the deadline anymore
+            executor::cancel_timer(timer);
+            callback(Ok(value));
+        }
+    }));
+}
+
+/// Ticks every `period`, starting one period from now
+///
+/// ```rust,ignore
+/// let every_second = async_runtime::interval(Duration::from_secs(1));
+/// loop {
+///     await!(every_second.tick());
+///     ..
+/// }
+/// ```
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::from_millis(0), "the period of an interval can't be zero");
+    Interval { start: Instant::now(), period }
+}
+
+/// Ticks at multiples of its period. Being `Copy`, it can be awaited
+/// and used again in the continuation.
+#[derive(Clone, Copy, Debug)]
+pub struct Interval {
+    start: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    /// Completes at the next multiple of the period. Ticks that were missed
+    /// because the caller was busy are skipped.
+    pub fn tick<F: FnOnce(()) + 'static>(self, callback: F) {
+        let periods = Instant::now().duration_since(self.start).as_nanos() / self.period.as_nanos() + 1;
+        let deadline = self.start + Duration::from_nanos((self.period.as_nanos() * periods) as u64);
+        sleep_until(deadline, callback);
+    }
+}
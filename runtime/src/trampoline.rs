@@ -0,0 +1,45 @@
+//! Runs continuations one after another instead of nested into each other.
+//! Used by `#[async(trampoline)]`, so awaits that complete synchronously
+//! don't grow the stack.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    static QUEUE: RefCell<VecDeque<Box<dyn FnOnce()>>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Wraps a continuation. Called while another bounced continuation runs further up the stack,
+/// it is queued and run once that one returns. Otherwise it runs right away, followed by every
+/// continuation queued in the meantime.
+pub fn bounce<T, F>(f: F) -> impl FnOnce(T)
+    where T: 'static,
+          F: FnOnce(T) + 'static
+{
+    move |value| {
+        if ACTIVE.with(|active| active.get()) {
+            QUEUE.with(|queue| queue.borrow_mut().push_back(Box::new(move || f(value))));
+            return;
+        }
+
+        ACTIVE.with(|active| active.set(true));
+        let _reset = Reset;
+
+        f(value);
+        while let Some(next) = QUEUE.with(|queue| queue.borrow_mut().pop_front()) {
+            next();
+        }
+    }
+}
+
+/// Leaves the trampoline, dropping the queued continuations if one of them panicked
+struct Reset;
+
+impl Drop for Reset {
+    fn drop(&mut self) {
+        let queued = QUEUE.with(|queue| queue.borrow_mut().split_off(0));
+        ACTIVE.with(|active| active.set(false));
+        drop(queued);
+    }
+}
@@ -1,41 +1,70 @@
-use super::{AwaitToCb, ConversionSess};
-use syntax::ext::build::AstBuilder;
-use syntax::ast::{Block, ExprKind};
-use syntax::ptr::P;
+use super::{scope, AwaitToCb, ConversionSess};
+use syn::{Block, Expr, ExprMacro, ExprReturn, Pat, Stmt};
+use syn::token;
 use std::vec::Vec;
 
-impl AwaitToCb for P<Block> {
-    fn await_to_cb<'a>(self,mut con: &'a mut ConversionSess) -> P<Block> {
+impl AwaitToCb for Block {
+    fn await_to_cb(self, con: &mut ConversionSess) -> Block {
         // Convert all statements inside the block
         let mut stmts = self.stmts.clone();
         // If there is an extra expression at the end of the block
         // it is converted into a return statement
-        if let Some(expr) = self.expr.clone() {
-            let expr = con.cx.stmt_expr(con.cx.expr(expr.span, ExprKind::Ret(Some(expr))));
-            stmts.push(expr.await_to_cb(con));
+        let tail = match stmts.last().cloned() {
+            Some(Stmt::Expr(expr, None)) => Some(expr),
+            // Macros with braces, like `select! { .. }`
+            Some(Stmt::Macro(mac)) if mac.semi_token.is_none() => {
+                Some(Expr::Macro(ExprMacro { attrs: mac.attrs, mac: mac.mac }))
+            }
+            _ => None,
+        };
+        if let Some(expr) = tail {
+            stmts.pop();
+            let expr = Expr::Return(ExprReturn {
+                attrs: Vec::new(),
+                return_token: token::Return::default(),
+                expr: Some(Box::new(expr)),
+            });
+            stmts.push(Stmt::Expr(expr, Some(token::Semi::default())));
         }
-	
-		// Since elements will be popped from the vector, the last ones will be handled first
-		stmts.reverse();
-		
-		
-		// Create new con
-		let mut con = ConversionSess::new(con.cx, con.final_cb);
-		con.remaining_stmts = stmts;
 
-        let mut stmts = Vec::new();
+        Block { stmts: convert_stmts(stmts, con), ..self }
+    }
+}
 
-		while let Some(stmt) = con.remaining_stmts.pop() {
-			stmts.push(stmt.clone().await_to_cb(&mut con));
-		}
+/// Converts a list of statements in a new session, placing the statements
+/// that follow an await inside of the generated callback
+pub fn convert_stmts(mut stmts: Vec<Stmt>, con: &ConversionSess) -> Vec<Stmt> {
+	// Since elements will be popped from the vector, the last ones will be handled first
+	stmts.reverse();
 
-		// If no callback was triggered we return the remaining stmts to the super conversion sess
-//		con.remaining_stmts.append(&mut sub_con.remaining_stmts.drain(..).rev().collect());
+	// Create new con
+	let mut con = con.nested();
+	con.remaining_stmts = stmts;
 
+    let mut stmts = Vec::new();
 
-        // Deref remaining stmts (if no callbacks were created) and collect
-        //        let stmts: Vec<Stmt> = con.remaining_stmts.map(|stmt| (*stmt).clone()).collect();
+	while let Some(stmt) = con.remaining_stmts.pop() {
+		stmts.push(stmt.await_to_cb(&mut con));
+	}
+
+    stmts
+}
 
-        con.cx.block(self.span, stmts, None)
+/// Converts a block nested in the current statement, keeping the value of its last expression.
+/// `pat` binds variables visible inside the block, like the pattern of a `match` arm.
+pub fn convert_inner(block: Block, con: &ConversionSess, pat: Option<&Pat>) -> Block {
+    let mut block_con = con.nested();
+    if let Some(pat) = pat {
+        scope::pat_bindings(pat, &mut block_con.locals);
     }
+
+    Block { stmts: convert_stmts(block.stmts, &block_con), ..block }
+}
+
+/// Whether the statements end by leaving with `return`, `break` or `continue`
+pub fn ends_in_jump(stmts: &[Stmt]) -> bool {
+    matches!(stmts.last(),
+             Some(Stmt::Expr(Expr::Break(_), _)) |
+             Some(Stmt::Expr(Expr::Continue(_), _)) |
+             Some(Stmt::Expr(Expr::Return(_), _)))
 }
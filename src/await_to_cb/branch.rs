@@ -0,0 +1,315 @@
+use super::{block, contains_await, exits_early, scope, AwaitToCb, Context, ConversionSess, Helper, LocalVar, LoopCtx, Suspension};
+use proc_macro2::{Ident, Span};
+use std::collections::HashSet;
+use syn::*;
+
+/// An `if`, `match`, block or `unsafe` block expression containing awaits. Like loops it is lowered
+/// once the statements following it are known, so it remembers its scope.
+pub struct Branch {
+    expr: Expr,
+    locals: Vec<LocalVar>,
+    loops: Vec<LoopCtx>,
+}
+
+/// Whether a branching expression has to wait for callbacks in one of its branches
+pub fn waits(expr: &Expr) -> bool {
+    match *expr {
+        Expr::If(ref expr) => {
+            contains_await(&expr.then_branch) ||
+            expr.else_branch.as_ref().is_some_and(|(_, else_branch)| contains_await(else_branch))
+        }
+        Expr::Match(ref expr) => expr.arms.iter().any(|arm| contains_await(&arm.body)),
+        Expr::Block(ref expr) => contains_await(&expr.block),
+        Expr::Unsafe(ref expr) => contains_await(&expr.block),
+        _ => false,
+    }
+}
+
+/// Whether the right side of `&&` or `||` awaits
+pub fn short_circuits(expr: &ExprBinary) -> bool {
+    matches!(expr.op, BinOp::And(_) | BinOp::Or(_)) && contains_await(&expr.right)
+}
+
+/// `a && b` and `a || b` as an `if` that only evaluates `b` when needed
+pub fn short_circuit(expr: ExprBinary) -> Expr {
+    let (left, right) = (&expr.left, &expr.right);
+    match expr.op {
+        BinOp::And(_) => parse_quote!(if #left { #right } else { false }),
+        _ => parse_quote!(if #left { true } else { #right }),
+    }
+}
+
+/// Whether a `match` has a guard that awaits
+pub fn guard_waits(expr: &ExprMatch) -> bool {
+    expr.arms.iter().any(|arm| arm.guard.as_ref().is_some_and(|(_, guard)| contains_await(guard)))
+}
+
+/// A `match` with a guard that awaits, split so the guard only runs once the arms before it
+/// didn't match and the value matches its pattern. The guard matches a reference to the value,
+/// its bindings are copied out of it if their type is `Copy` and stay references otherwise,
+/// like bindings with `ref`. A place expression like `user.role` is matched again by the nested
+/// matches, like the original arms would, anything else is moved into a binding.
+///
+/// ```rust,ignore
+/// match value {
+///     before => ..,
+///     __rust_async_autogen_matched => {
+///         if match &__rust_async_autogen_matched { pat => { let id = copied(id); guard }, _ => false } {
+///             match __rust_async_autogen_matched { pat => body, _ => unreachable!() }
+///         } else {
+///             match __rust_async_autogen_matched { after.., _ => unreachable!() }
+///         }
+///     }
+/// }
+/// ```
+pub fn lazy_guard(expr: ExprMatch, cx: &Context) -> Expr {
+    let waits = |arm: &Arm| arm.guard.as_ref().is_some_and(|(_, guard)| contains_await(guard));
+    let index = expr.arms.iter().position(waits).expect("one of the guards awaits");
+
+    let mut arms = expr.arms;
+    let after = arms.split_off(index + 1);
+    let arm = arms.pop().unwrap();
+    let (pat, guard, body) = (arm.pat, arm.guard.unwrap().1, arm.body);
+    let (catch_all, matched): (Pat, Expr) = if is_place(&expr.expr) {
+        (parse_quote!(_), (*expr.expr).clone())
+    } else {
+        let matched = Ident::new("__rust_async_autogen_matched", Span::call_site());
+        (parse_quote!(#matched), parse_quote!(#matched))
+    };
+
+    let mut bindings = Vec::new();
+    scope::pat_bindings(&pat, &mut bindings);
+    let by_ref = ref_bindings(&pat);
+    let copied: Vec<Stmt> = bindings.iter()
+        .filter(|var| !by_ref.contains(&var.ident))
+        .map(|var| {
+            let ident = &var.ident;
+            parse_quote!(let #ident = __RustAsyncAutogenGuard(#ident).value();)
+        })
+        .collect();
+    if !copied.is_empty() {
+        cx.use_helper(Helper::Guard);
+    }
+
+    arms.push(parse_quote! {
+        #catch_all => {
+            if match &#matched { #pat => { #(#copied)* #guard }, _ => false } {
+                match #matched {
+                    // The bindings might only be used by the guard
+                    #[allow(unused_variables)]
+                    #pat => #body,
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!(),
+                }
+            } else {
+                match #matched {
+                    #(#after)*
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!(),
+                }
+            }
+        }
+    });
+    Expr::Match(ExprMatch { arms, ..expr })
+}
+
+/// Variables a pattern binds with `ref` or `ref mut`
+fn ref_bindings(pat: &Pat) -> Vec<Ident> {
+    struct RefBindings(Vec<Ident>);
+
+    impl<'ast> visit::Visit<'ast> for RefBindings {
+        fn visit_pat_ident(&mut self, pat: &'ast PatIdent) {
+            if pat.by_ref.is_some() {
+                self.0.push(pat.ident.clone());
+            }
+            visit::visit_pat_ident(self, pat);
+        }
+    }
+
+    let mut visitor = RefBindings(Vec::new());
+    visit::Visit::visit_pat(&mut visitor, pat);
+    visitor.0
+}
+
+/// Local variables and their fields, which can be matched again without side effects
+fn is_place(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Path(ref path) => path.qself.is_none() && path.path.segments.len() == 1,
+        Expr::Field(ref field) => is_place(&field.base),
+        Expr::Paren(ref paren) => is_place(&paren.expr),
+        _ => false,
+    }
+}
+
+/// Turns a branching expression with awaits into a suspension point.
+/// The condition or the matched value is evaluated before the branches run.
+pub fn hoist(expr: Expr, con: &mut ConversionSess) -> Expr {
+    let expr = match expr {
+        Expr::If(expr) => Expr::If(ExprIf { cond: expr.cond.await_to_cb(con), ..expr }),
+        Expr::Match(expr) => Expr::Match(ExprMatch { expr: expr.expr.await_to_cb(con), ..expr }),
+        Expr::Block(ref expr) if expr.label.is_some() => {
+            con.cx.span_err(expr.block.brace_token.span.join(),
+                            "await can't be used inside of labeled blocks");
+            return Expr::Block(expr.clone());
+        }
+        expr => expr,
+    };
+
+    let branch = Branch {
+        expr,
+        locals: con.locals.clone(),
+        loops: con.loops.clone(),
+    };
+    con.suspend(Suspension::Branch(branch))
+}
+
+/// Lowers a branching expression into a closure that runs the taken branch and
+/// ends it by calling a shared join continuation with the value of the branch.
+/// `inner_stmts` make up the join continuation, receiving the value as `param`.
+/// Variables used after the branch are passed along, so the branches may change them.
+pub fn lower(branch: Branch, con: &ConversionSess, param: Pat, inner_stmts: Vec<Stmt>) -> Stmt {
+    let cx = con.cx;
+    cx.use_helper(Helper::Branch);
+
+    let id = cx.next_id();
+    let join_ident = cx.ident_of(&format!("__rust_async_autogen_join{}", id));
+    let state_ident = cx.ident_of("__rust_async_autogen_state");
+
+    let used_after = scope::free_vars(&inner_stmts);
+    let vars: Vec<LocalVar> = scope::live_vars(&branch.locals, &used_after)
+        .into_iter()
+        .filter(|var| used_after.contains(&var.ident.to_string()))
+        .collect();
+    for var in &vars {
+        cx.thread_var(var);
+    }
+
+    let mut body_con = con.nested();
+    body_con.locals = branch.locals;
+    body_con.locals.push(LocalVar::generated(join_ident.clone(), false));
+    body_con.loops = branch.loops;
+
+    let join = Join {
+        ident: join_ident,
+        vars: scope::vars_expr(&vars),
+        names: vars.iter().map(|var| var.ident.to_string()).collect(),
+    };
+
+    let body: Vec<Stmt> = match branch.expr {
+        Expr::If(expr) => vec![Stmt::Expr(Expr::If(lower_if(expr, &body_con, &join)), None)],
+        Expr::Match(expr) => {
+            let arms = expr.arms
+                           .into_iter()
+                           .map(|arm| {
+                               let stmts = arm_stmts(*arm.body);
+                               let block = join.lower_arm(stmts, &body_con, Some(&arm.pat));
+                               Arm { body: Box::new(block_expr(block)), ..arm }
+                           })
+                           .collect();
+            vec![Stmt::Expr(Expr::Match(ExprMatch { arms, ..expr }), None)]
+        }
+        Expr::Block(expr) => join.lower_arm(expr.block.stmts, &body_con, None).stmts,
+        // The continuations are written inside of the block, so they can use unsafe operations as well
+        Expr::Unsafe(expr) => {
+            let block = join.lower_arm(expr.block.stmts, &body_con, None);
+            vec![Stmt::Expr(Expr::Unsafe(ExprUnsafe { block, ..expr }), None)]
+        }
+        _ => unreachable!(),
+    };
+
+    let join_ident = &join.ident;
+    let vars_pat = scope::vars_pat(&vars);
+    cx.check_captured_assignments(&body, &con.locals);
+
+    parse_quote! {
+        __rust_async_autogen_branch(
+            move |#join_ident| { #(#body)* },
+            move |#state_ident| {
+                #[allow(unused_mut, unused_variables)]
+                let (#vars_pat, #param) = #state_ident;
+                #(#inner_stmts)*
+            },
+        );
+    }
+}
+
+/// The continuation every branch ends with
+struct Join {
+    ident: Ident,
+    /// Variables passed on to the statements after the branch
+    vars: Expr,
+    names: HashSet<String>,
+}
+
+impl Join {
+    /// Converts the statements of a branch, handing its value to the join continuation
+    fn lower_arm(&self, mut stmts: Vec<Stmt>, con: &ConversionSess, pat: Option<&Pat>) -> Block {
+        let mut arm_con = con.nested();
+        if let Some(pat) = pat {
+            scope::pat_bindings(pat, &mut arm_con.locals);
+        }
+
+        // Branches leaving with `return`, `break` or `continue` never reach the join
+        if !block::ends_in_jump(&stmts) {
+            let value = match stmts.pop() {
+                Some(Stmt::Expr(expr, None)) => expr,
+                Some(stmt) => {
+                    stmts.push(stmt);
+                    parse_quote!(())
+                }
+                None => parse_quote!(()),
+            };
+            let (join_ident, vars) = (&self.ident, &self.vars);
+            // The value is evaluated before the join takes the variables, `?` or the value itself
+            // may still need them
+            let uses_vars = || !scope::free_vars(&value).is_disjoint(&self.names);
+            let value = if exits_early(&value) || uses_vars() {
+                let value_ident = con.cx.ident_of("__rust_async_autogen_value");
+                stmts.push(parse_quote!(let #value_ident = #value;));
+                parse_quote!(#value_ident)
+            } else {
+                value
+            };
+            // Nothing follows a branch in its closure, so the join doesn't need a `return`
+            stmts.push(parse_quote!(#join_ident((#vars, #value));));
+        }
+
+        Block {
+            brace_token: Default::default(),
+            stmts: block::convert_stmts(stmts, &arm_con),
+        }
+    }
+}
+
+fn lower_if(expr: ExprIf, con: &ConversionSess, join: &Join) -> ExprIf {
+    let pat = match *expr.cond {
+        Expr::Let(ref expr_let) => Some(&*expr_let.pat),
+        _ => None,
+    };
+    let then_branch = join.lower_arm(expr.then_branch.stmts.clone(), con, pat);
+
+    // `else if` continues inside the else branch, where it is converted like any other expression
+    let else_stmts = match expr.else_branch {
+        Some((_, ref else_branch)) => arm_stmts((**else_branch).clone()),
+        None => Vec::new(),
+    };
+    let else_branch = join.lower_arm(else_stmts, con, None);
+
+    ExprIf {
+        then_branch,
+        else_branch: Some((Default::default(), Box::new(block_expr(else_branch)))),
+        ..expr
+    }
+}
+
+/// Statements of a branch, its value being the tail expression
+fn arm_stmts(body: Expr) -> Vec<Stmt> {
+    match body {
+        Expr::Block(ExprBlock { block, label: None, ref attrs }) if attrs.is_empty() => block.stmts,
+        body => vec![Stmt::Expr(body, None)],
+    }
+}
+
+fn block_expr(block: Block) -> Expr {
+    Expr::Block(ExprBlock { attrs: Vec::new(), label: None, block })
+}
@@ -1,151 +1,304 @@
-use syntax::ext::quote::rt::ToTokens;
-use super::{AwaitToCb, ConversionSess};
-use syntax::ext::build::AstBuilder;
-use syntax::ast::*;
-use syntax::ptr::P;
-use syntax::parse::token;
+use super::{block, branch, contains_await, eval_first, loops, scope, select, support, AwaitToCb,
+            Context, ConversionSess, Helper, Suspension, AWAIT_EACH_IDENT, AWAIT_IDENT, JOIN_IDENT,
+            SELECT_IDENT};
+use syn::*;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
 
-impl AwaitToCb for P<Expr> {
+impl AwaitToCb for Expr {
     fn await_to_cb(self, con: &mut ConversionSess) -> Self {
-        let Expr {node: expr_kind, span, ..} = (*self).clone();
-
-        let expr_kind = match expr_kind {
-            ExprKind::Box(expr) => ExprKind::Box(expr.await_to_cb(con)),
-            ExprKind::InPlace(expr1, expr2) => {
-                ExprKind::InPlace(expr1.await_to_cb(con), expr2.await_to_cb(con))
-            }
-            ExprKind::Vec(exprs) => ExprKind::Vec(exprs.await_to_cb(con)),
-            ExprKind::Call(func, args) => ExprKind::Call(func, args.await_to_cb(con)),
-            ExprKind::MethodCall(ident, func, args) => {
-                ExprKind::MethodCall(ident, func, args.await_to_cb(con))
-            }
-            ExprKind::Tup(exprs) => ExprKind::Tup(exprs.await_to_cb(con)),
-            ExprKind::Binary(op, expr1, expr2) => {
-                ExprKind::Binary(op, expr1.await_to_cb(con), expr2.await_to_cb(con))
-            }
-            ExprKind::Unary(un_op, expr) => ExprKind::Unary(un_op, expr.await_to_cb(con)),
-            ExprKind::Cast(expr, ty) => ExprKind::Cast(expr.await_to_cb(con), ty),
-            ExprKind::Type(expr, ty) => ExprKind::Type(expr.await_to_cb(con), ty),
-            ExprKind::If(comparison_expr, block, extra_expr) => {
-                ExprKind::If(comparison_expr.await_to_cb(con),
-                             block.await_to_cb(con),
-                             extra_expr.await_to_cb(con))
-            }
-            ExprKind::IfLet(pat, comparison_expr, block, extra_expr) => {
-                ExprKind::IfLet(pat,
-                                comparison_expr.await_to_cb(con),
-                                block.await_to_cb(con),
-                                extra_expr.await_to_cb(con))
-            }
-            ExprKind::While(expr, block, ident) => {
-                ExprKind::While(expr.await_to_cb(con), block.await_to_cb(con), ident)
-            }
-            ExprKind::WhileLet(pat, expr, block, ident) => {
-                ExprKind::WhileLet(pat, expr.await_to_cb(con), block.await_to_cb(con), ident)
-            }
-            ExprKind::ForLoop(pat, expr, block, ident) => {
-                ExprKind::ForLoop(pat, expr.await_to_cb(con), block.await_to_cb(con), ident)
-            }
-            ExprKind::Loop(block, ident) => ExprKind::Loop(block.await_to_cb(con), ident),
-            ExprKind::Match(expr, arms) => {
-                let arms = arms.into_iter()
-                               .map(|arm| Arm { body: arm.body.await_to_cb(con), ..arm })
+        match self {
+            Expr::Array(expr) => Expr::Array(ExprArray { elems: expr.elems.await_to_cb(con), ..expr }),
+            // The callee and receiver are evaluated before the arguments
+            Expr::Call(expr) => {
+                Expr::Call(ExprCall {
+                    func: expr.func.await_to_cb(con),
+                    args: expr.args.await_to_cb(con),
+                    ..expr
+                })
+            }
+            Expr::MethodCall(expr) => {
+                Expr::MethodCall(ExprMethodCall {
+                    receiver: expr.receiver.await_to_cb(con),
+                    args: expr.args.await_to_cb(con),
+                    ..expr
+                })
+            }
+            Expr::Tuple(expr) => Expr::Tuple(ExprTuple { elems: expr.elems.await_to_cb(con), ..expr }),
+            // Awaits on the right of `&&` and `||` only start once the left side didn't decide
+            Expr::Binary(expr) if branch::short_circuits(&expr) => branch::short_circuit(expr).await_to_cb(con),
+            Expr::Binary(expr) => {
+                Expr::Binary(ExprBinary {
+                    left: expr.left.await_to_cb(con),
+                    right: expr.right.await_to_cb(con),
+                    ..expr
+                })
+            }
+            Expr::Unary(expr) => Expr::Unary(ExprUnary { expr: expr.expr.await_to_cb(con), ..expr }),
+            Expr::Cast(expr) => Expr::Cast(ExprCast { expr: expr.expr.await_to_cb(con), ..expr }),
+            Expr::Let(expr) => Expr::Let(ExprLet { expr: expr.expr.await_to_cb(con), ..expr }),
+            expr @ Expr::If(_) if branch::waits(&expr) => branch::hoist(expr, con),
+            Expr::If(expr) => {
+                let cond = expr.cond.await_to_cb(con);
+                let then_branch = match *cond {
+                    Expr::Let(ref expr_let) => block::convert_inner(expr.then_branch, con, Some(&expr_let.pat)),
+                    _ => block::convert_inner(expr.then_branch, con, None),
+                };
+                Expr::If(ExprIf {
+                    cond,
+                    then_branch,
+                    else_branch: expr.else_branch
+                                     .map(|(else_token, expr)| (else_token, expr.await_to_cb(con))),
+                    ..expr
+                })
+            }
+            expr @ Expr::While(_) => loops::hoist(expr, con),
+            expr @ Expr::ForLoop(_) => loops::hoist(expr, con),
+            expr @ Expr::Loop(_) => loops::hoist(expr, con),
+            Expr::Break(expr) => loops::convert_break(expr, con),
+            Expr::Continue(expr) => loops::convert_continue(expr, con),
+            Expr::Match(expr) if branch::guard_waits(&expr) => branch::lazy_guard(expr, con.cx).await_to_cb(con),
+            expr @ Expr::Match(_) if branch::waits(&expr) => branch::hoist(expr, con),
+            Expr::Match(expr) => {
+                let scrutinee = expr.expr.await_to_cb(con);
+                let arms = expr.arms
+                               .into_iter()
+                               .map(|arm| {
+                                   let mut arm_con = con.nested();
+                                   scope::pat_bindings(&arm.pat, &mut arm_con.locals);
+                                   Arm { body: arm.body.await_to_cb(&mut arm_con), ..arm }
+                               })
                                .collect();
-                ExprKind::Match(expr.await_to_cb(con), arms)
+                Expr::Match(ExprMatch { expr: scrutinee, arms, ..expr })
             }
-            ExprKind::Closure(capture_by, fn_decl, block) => {
-                ExprKind::Closure(capture_by, fn_decl, block.await_to_cb(con))
+            Expr::Closure(expr) => {
+                // A closure is a separate function, its body runs whenever the closure is called
+                if contains_await(&expr.body) {
+                    con.cx.span_err(expr.span(), "await can't be used inside of closures");
+                }
+                Expr::Closure(expr)
             }
-            ExprKind::Block(block) => ExprKind::Block(block.await_to_cb(con)),
-            ExprKind::Assign(expr1, expr2) => {
-                ExprKind::Assign(expr1.await_to_cb(con), expr2.await_to_cb(con))
+            expr @ Expr::Block(_) if branch::waits(&expr) => branch::hoist(expr, con),
+            Expr::Block(expr) => Expr::Block(ExprBlock { block: block::convert_inner(expr.block, con, None), ..expr }),
+            expr @ Expr::Unsafe(_) if branch::waits(&expr) => branch::hoist(expr, con),
+            Expr::Unsafe(expr) => Expr::Unsafe(ExprUnsafe { block: block::convert_inner(expr.block, con, None), ..expr }),
+            Expr::Const(expr) => {
+                // A const block is evaluated at compile time
+                if contains_await(&expr.block) {
+                    con.cx.span_err(expr.span(), "await can't be used inside of const blocks");
+                }
+                Expr::Const(expr)
             }
-            ExprKind::AssignOp(bin_op, expr1, expr2) => {
-                ExprKind::AssignOp(bin_op, expr1.await_to_cb(con), expr2.await_to_cb(con))
+            // Expressions passed through `macro_rules!` fragments are wrapped in invisible groups
+            Expr::Group(expr) => Expr::Group(ExprGroup { expr: expr.expr.await_to_cb(con), ..expr }),
+            Expr::Assign(expr) => {
+                Expr::Assign(ExprAssign {
+                    left: expr.left.await_to_cb(con),
+                    right: expr.right.await_to_cb(con),
+                    ..expr
+                })
             }
-            ExprKind::Field(expr, ident) => ExprKind::Field(expr.await_to_cb(con), ident),
-            ExprKind::TupField(expr, size) => ExprKind::TupField(expr.await_to_cb(con), size),
-            ExprKind::Index(expr1, expr2) => {
-                ExprKind::Index(expr1.await_to_cb(con), expr2.await_to_cb(con))
+            Expr::Field(expr) => Expr::Field(ExprField { base: expr.base.await_to_cb(con), ..expr }),
+            Expr::Index(expr) => {
+                Expr::Index(ExprIndex {
+                    expr: expr.expr.await_to_cb(con),
+                    index: expr.index.await_to_cb(con),
+                    ..expr
+                })
             }
-            ExprKind::Range(expr1, expr2, limits) => {
-                ExprKind::Range(expr1.await_to_cb(con), expr2.await_to_cb(con), limits)
+            Expr::Range(expr) => {
+                Expr::Range(ExprRange {
+                    start: expr.start.await_to_cb(con),
+                    end: expr.end.await_to_cb(con),
+                    ..expr
+                })
             }
-            ExprKind::AddrOf(mutability, expr) => ExprKind::AddrOf(mutability, expr.await_to_cb(con)),
-            ExprKind::InlineAsm(inline_asm) => {
-                let inputs = inline_asm.inputs.into_iter()
-                               .map(|(interned_str, expr)| (interned_str, expr.await_to_cb(con)))
-                               .collect();
-             	ExprKind::InlineAsm(InlineAsm {inputs: inputs, .. inline_asm})                  
+            Expr::Reference(expr) => {
+                Expr::Reference(ExprReference { expr: expr.expr.await_to_cb(con), ..expr })
             }
-            ExprKind::Struct(path, fields, expr) => {
-                let fields = fields.into_iter()
-                               .map(|field| Field {expr: field.expr.await_to_cb(con), .. field})
-                               .collect();
-            	ExprKind::Struct(path, fields, expr.await_to_cb(con))
+            Expr::Struct(expr) => {
+                let fields = expr.fields
+                                 .into_pairs()
+                                 .map(|pair| {
+                                     let (field, punct) = pair.into_tuple();
+                                     let field = FieldValue { expr: field.expr.await_to_cb(con), ..field };
+                                     punctuated::Pair::new(field, punct)
+                                 })
+                                 .collect();
+                Expr::Struct(ExprStruct { fields, rest: expr.rest.await_to_cb(con), ..expr })
             }
-            ExprKind::Repeat(expr1, expr2) => {
-                ExprKind::Repeat(expr1.await_to_cb(con), expr2.await_to_cb(con))
+            Expr::Repeat(expr) => {
+                Expr::Repeat(ExprRepeat {
+                    expr: expr.expr.await_to_cb(con),
+                    len: expr.len.await_to_cb(con),
+                    ..expr
+                })
             }
-            ExprKind::Ret(Some(expr)) => {
+            Expr::Return(ExprReturn { expr, attrs, return_token }) => {
                 let expr = expr.await_to_cb(con);
                 // If this function returns something it will have a final callback
-                // that should be called instead of a sync return
+                // that should be called instead of a sync return.
+                // Returning afterwards makes sure no other continuation runs.
                 if con.final_cb {
-                    quote_expr!(con.cx, __rust_async_autogen_final_callback($expr)).node.clone()
+                    let expr = match expr {
+                        Some(expr) => *expr,
+                        // Leaving a stream ends it
+                        None if con.cx.options.stream => {
+                            return parse_quote! {
+                                return __rust_async_autogen_final_callback(::async_runtime::stream::Step::Done)
+                            };
+                        }
+                        None => parse_quote!(()),
+                    };
+                    eval_first(con.cx, expr, |value| finish(con.cx, value))
                 } else {
-                    ExprKind::Ret(Some(expr))
+                    Expr::Return(ExprReturn { expr, attrs, return_token })
                 }
             }
-            ExprKind::Paren(expr) => ExprKind::Paren(expr.await_to_cb(con)),
-            ExprKind::Mac(mac) => {
-                // Macros should always have one path segment
-                let identifier = mac.node.path.segments.first().unwrap().identifier;
-
-                let inner_tokens = mac.node.tts.clone();
+            Expr::Try(expr) => {
+                let inner = expr.expr.await_to_cb(con);
+                // An error is handed to the final callback right away,
+                // none of the remaining continuations run
+                if con.final_cb && con.cx.returns_option {
+                    let finish = finish(con.cx, &parse_quote!(::std::option::Option::None));
+                    parse_quote! {
+                        match #inner {
+                            ::std::option::Option::Some(__rust_async_autogen_value) => __rust_async_autogen_value,
+                            ::std::option::Option::None => {
+                                #finish;
+                            }
+                        }
+                    }
+                } else if con.final_cb {
+                    let error = parse_quote! {
+                        ::std::result::Result::Err(::std::convert::From::from(__rust_async_autogen_error))
+                    };
+                    let finish = finish(con.cx, &error);
+                    parse_quote! {
+                        match #inner {
+                            ::std::result::Result::Ok(__rust_async_autogen_value) => __rust_async_autogen_value,
+                            ::std::result::Result::Err(__rust_async_autogen_error) => {
+                                #finish;
+                            }
+                        }
+                    }
+                } else {
+                    Expr::Try(ExprTry { expr: inner, ..expr })
+                }
+            }
+            Expr::Paren(expr) => Expr::Paren(ExprParen { expr: expr.expr.await_to_cb(con), ..expr }),
+            Expr::Macro(expr) => {
+                let span = expr.span();
 
-                if con.cx.ident_of("await").name == identifier.name {
-                    let expr = quote_expr!(con.cx, $inner_tokens).await_to_cb(con);
+                if expr.mac.path.is_ident(AWAIT_IDENT) {
+                    let inner = match expr.mac.parse_body::<Expr>() {
+                        Ok(inner) => inner.await_to_cb(con),
+                        Err(_) => {
+                            con.cx.span_err(span, "await macro expects a single function call as a \
+                                                   parameter\nlike: await!(get_user(1))");
+                            return Expr::Macro(expr);
+                        }
+                    };
 
-                    // Was a single function provided inside await?
-                    match expr.node.clone() {
-                        expr_kind @ ExprKind::Call(..) => con.await_functions.push(expr_kind),
-                        expr_kind @ ExprKind::MethodCall(..) => con.await_functions.push(expr_kind),
+                    con.suspend(Suspension::Call(awaited(inner, span)))
+                } else if expr.mac.path.is_ident(JOIN_IDENT) {
+                    let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
+                    let started = match parser.parse2(expr.mac.tokens.clone()) {
+                        Ok(calls) if !calls.is_empty() => calls.await_to_cb(con),
                         _ => {
-                            con.cx.span_err(expr.span,
-                                            "await macro expects a single function call as a \
-                                             parameter\nlike: await!(get_user(1))")
+                            con.cx.span_err(span, "join macro expects function calls separated by commas\n\
+                                                   like: join!(get_user(1), get_user(2))");
+                            return Expr::Macro(expr);
                         }
                     };
-					let var_ident = con.cx.ident_of(&format!("__rust_async_autogen_callback{}", con.await_functions.len()));
 
-                    quote_expr!(con.cx, $var_ident).node.clone()
-                } else {
-                    // Parse macro arguments into as a tuple
-                    // then search expressions inside tuple for await functions
-                    if let ExprKind::Tup(exprs) = quote_expr!(con.cx, ($inner_tokens))
-                                                      .node
-                                                      .clone() {
-                        let mut processed_exprs = Vec::new();
+                    // Every call is started right away, one statement each, so they only borrow
+                    // their arguments for as long as the call. The continuation is handed over last.
+                    let calls = started.len();
+                    con.cx.use_helper(Helper::Join(calls));
+                    let state_ident = con.cx.ident_of("__rust_async_autogen_join");
+                    let state = support::join_ident(calls);
+                    let starts = started.into_iter().enumerate().map(|(i, call)| -> Stmt {
+                        let callback = support::callback_ident(i);
+                        let call = with_callback(awaited(call, span), parse_quote!(#state_ident.#callback()))
+                            .expect("awaited expressions are calls");
+                        parse_quote!(#call;)
+                    });
+                    let starts: Vec<Stmt> = starts.collect();
 
-                        for expr in exprs {
-                            // Add comma if we already have and expression
-                            if !processed_exprs.is_empty() {
-                                processed_exprs.push(TokenTree::Token(span, token::Token::Comma));
-                            }
+                    con.suspend(Suspension::Call(parse_quote_spanned!(span=> ({
+                        let #state_ident = #state::new();
+                        #(#starts)*
+                        #state_ident
+                    }).join())))
+                } else if expr.mac.path.is_ident(SELECT_IDENT) {
+                    select::lower(expr, con, span)
+                } else if expr.mac.path.is_ident(AWAIT_EACH_IDENT) {
+                    // `for` loops take it apart before their iterator is converted
+                    con.cx.span_err(span, "await_each can only be iterated over by a for loop\n\
+                                           like: for user in await_each!(users) { .. }");
+                    Expr::Macro(expr)
+                } else {
+                    // Parse macro arguments as a comma separated list of expressions
+                    // then search the expressions for await functions
+                    let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
 
-                            processed_exprs.append(&mut expr.await_to_cb(con).to_tokens(con.cx));
-                        }
+                    if let Ok(exprs) = parser.parse2(expr.mac.tokens.clone()) {
+                        let exprs = exprs.await_to_cb(con);
+                        let mut mac = expr.mac;
+                        mac.tokens = quote!(#exprs);
 
-                        quote_expr!(con.cx, $identifier!($processed_exprs)).node.clone()
+                        Expr::Macro(ExprMacro { mac, ..expr })
                     } else {
-                        quote_expr!(con.cx, $identifier!($inner_tokens)).node.clone()
+                        Expr::Macro(expr)
                     }
                 }
             }
-            _ => expr_kind,
-        };
+            expr => expr,
+        }
+    }
+}
+
+/// Returns the value of the function through its final callback.
+/// Cancellable functions complete with `Ok` unless they were cancelled,
+/// streams yield the value as their last one.
+fn finish(cx: &Context, value: &Expr) -> Expr {
+    if cx.options.stream {
+        parse_quote!(return ::async_runtime::stream::yield_last(#value, __rust_async_autogen_final_callback))
+    } else if cx.cancellation_token.is_some() {
+        parse_quote!(return __rust_async_autogen_final_callback(::std::result::Result::Ok(#value)))
+    } else {
+        parse_quote!(return __rust_async_autogen_final_callback(#value))
+    }
+}
+
+/// The call an awaited expression turns into. Calls take the continuation as their last argument,
+/// anything else is a future, a call returning one can be put in parentheses.
+pub fn awaited(inner: Expr, span: proc_macro2::Span) -> Expr {
+    match inner {
+        Expr::Call(..) | Expr::MethodCall(..) => inner,
+        inner => {
+            let future = match inner {
+                Expr::Paren(paren) => *paren.expr,
+                inner => inner,
+            };
+            parse_quote_spanned!(span=> ::async_runtime::future::await_future(#future))
+        }
+    }
+}
 
-        con.cx.expr(span, expr_kind)
+/// Appends the callback to the arguments of a call
+pub fn with_callback(call: Expr, callback: Expr) -> Option<Expr> {
+    match call {
+        Expr::Call(mut call) => {
+            call.args.push(callback);
+            Some(Expr::Call(call))
+        }
+        Expr::MethodCall(mut method_call) => {
+            method_call.args.push(callback);
+            Some(Expr::MethodCall(method_call))
+        }
+        _ => None,
     }
 }
@@ -0,0 +1,358 @@
+use super::{block, contains_await, eval_first, scope, AwaitToCb, ConversionSess, Helper, LocalVar, Suspension,
+            AWAIT_EACH_IDENT};
+use proc_macro2::Ident;
+use syn::*;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+
+/// A loop surrounding the statement that is being converted
+#[derive(Clone)]
+pub enum LoopCtx {
+    /// Loop without awaits, `break` and `continue` are left untouched
+    Sync(Option<Lifetime>),
+    /// Loop lowered into a recursive continuation
+    Lowered(Lowered),
+}
+
+#[derive(Clone)]
+pub struct Lowered {
+    label: Option<Lifetime>,
+    /// Runs the next iteration
+    loop_ident: Ident,
+    /// Continues with the statements after the loop
+    break_ident: Ident,
+    /// Variables passed from one iteration to the next
+    vars_iter: Vec<LocalVar>,
+    /// Variables handed back to the statements after the loop
+    vars_exit: Vec<LocalVar>,
+}
+
+impl LoopCtx {
+    fn label(&self) -> Option<&Lifetime> {
+        match *self {
+            LoopCtx::Sync(ref label) => label.as_ref(),
+            LoopCtx::Lowered(ref lowered) => lowered.label.as_ref(),
+        }
+    }
+}
+
+/// A loop containing awaits. It is lowered once the statements following it are known,
+/// so it remembers the scope it was found in.
+pub struct Loop {
+    expr: Expr,
+    /// The `for` loop iterates over a stream with `await_each!`, its iterator is the stream
+    each: bool,
+    locals: Vec<LocalVar>,
+    loops: Vec<LoopCtx>,
+}
+
+/// Converts a loop, turning it into a suspension point if its body waits for callbacks
+pub fn hoist(expr: Expr, con: &mut ConversionSess) -> Expr {
+    let waits = match expr {
+        Expr::While(ref expr) => contains_await(&expr.cond) || contains_await(&expr.body),
+        Expr::ForLoop(ref expr) => is_await_each(&expr.expr) || contains_await(&expr.body),
+        Expr::Loop(ref expr) => contains_await(&expr.body),
+        _ => false,
+    };
+
+    if !waits {
+        return convert_sync(expr, con);
+    }
+
+    // The iterator or stream is created once before the loop starts
+    let mut each = false;
+    let expr = match expr {
+        Expr::ForLoop(expr) => {
+            let iter = match *expr.expr {
+                Expr::Macro(ref mac) if is_await_each(&expr.expr) => match mac.mac.parse_body::<Expr>() {
+                    Ok(stream) => {
+                        each = true;
+                        Box::new(stream.await_to_cb(con))
+                    }
+                    Err(_) => {
+                        con.cx.span_err(mac.span(), "await_each macro expects the stream to iterate over\n\
+                                                     like: for user in await_each!(users) { .. }");
+                        expr.expr.clone()
+                    }
+                },
+                _ => expr.expr.clone().await_to_cb(con),
+            };
+            Expr::ForLoop(ExprForLoop { expr: iter, ..expr })
+        }
+        expr => expr,
+    };
+
+    let lowered_loop = Loop {
+        expr,
+        each,
+        locals: con.locals.clone(),
+        loops: con.loops.clone(),
+    };
+    con.suspend(Suspension::Loop(lowered_loop))
+}
+
+/// Converts the body of a loop that stays a regular rust loop
+fn convert_sync(expr: Expr, con: &mut ConversionSess) -> Expr {
+    let mut body_con = con.nested();
+
+    match expr {
+        Expr::While(expr) => {
+            let cond = expr.cond.await_to_cb(con);
+            if let Expr::Let(ref expr_let) = *cond {
+                scope::pat_bindings(&expr_let.pat, &mut body_con.locals);
+            }
+            body_con.loops.push(LoopCtx::Sync(expr.label.as_ref().map(|label| label.name.clone())));
+            let body = Block { stmts: block::convert_stmts(expr.body.stmts, &body_con), ..expr.body };
+
+            Expr::While(ExprWhile { cond, body, ..expr })
+        }
+        Expr::ForLoop(expr) => {
+            let iter = expr.expr.await_to_cb(con);
+            scope::pat_bindings(&expr.pat, &mut body_con.locals);
+            body_con.loops.push(LoopCtx::Sync(expr.label.as_ref().map(|label| label.name.clone())));
+            let body = Block { stmts: block::convert_stmts(expr.body.stmts, &body_con), ..expr.body };
+
+            Expr::ForLoop(ExprForLoop { expr: iter, body, ..expr })
+        }
+        Expr::Loop(expr) => {
+            body_con.loops.push(LoopCtx::Sync(expr.label.as_ref().map(|label| label.name.clone())));
+            let body = Block { stmts: block::convert_stmts(expr.body.stmts, &body_con), ..expr.body };
+
+            Expr::Loop(ExprLoop { body, ..expr })
+        }
+        expr => expr,
+    }
+}
+
+/// Lowers a loop into a function that runs one iteration and calls itself again
+/// from the callback of the last await in the iteration. The loop's variables are
+/// passed from one iteration to the next, so the iteration can't capture anything.
+/// `inner_stmts` run once the loop finishes, receiving the value of `break` as `param`.
+pub fn lower(lowered_loop: Loop, con: &ConversionSess, param: Pat, inner_stmts: Vec<Stmt>) -> Stmt {
+    let cx = con.cx;
+    cx.use_helper(Helper::Branch);
+    cx.use_helper(Helper::Loop);
+
+    let id = cx.next_id();
+    let loop_ident = cx.ident_of(&format!("__rust_async_autogen_loop{}", id));
+    let break_ident = cx.ident_of(&format!("__rust_async_autogen_break{}", id));
+    let state_ident = cx.ident_of("__rust_async_autogen_state");
+
+    let mut prelude: Vec<Stmt> = Vec::new();
+    let mut iteration: Vec<Stmt> = Vec::new();
+    let mut generated_vars = Vec::new();
+
+    let (label, body) = match lowered_loop.expr {
+        Expr::While(expr) => {
+            iteration.push(match *expr.cond {
+                Expr::Let(ExprLet { pat, expr: init, .. }) => parse_quote!(let #pat = #init else { break; };),
+                cond => parse_quote!(if !(#cond) { break; }),
+            });
+
+            (expr.label, expr.body)
+        }
+        Expr::ForLoop(expr) if lowered_loop.each => {
+            let stream_ident = cx.ident_of(&format!("__rust_async_autogen_stream{}", id));
+            let (pat, stream) = (expr.pat, expr.expr);
+
+            // Each iteration starts by waiting for the next value, the loop ends with the stream
+            prelude.push(parse_quote!(let #stream_ident: ::async_runtime::stream::AsyncStream<_> = #stream;));
+            iteration.push(parse_quote! {
+                let ::std::option::Option::Some(#pat) = __rust_async_await!(#stream_ident.clone().next()) else {
+                    break;
+                };
+            });
+            generated_vars.push(LocalVar::generated(stream_ident, false));
+
+            (expr.label, expr.body)
+        }
+        Expr::ForLoop(expr) => {
+            let iter_ident = cx.ident_of(&format!("__rust_async_autogen_iter{}", id));
+            let (pat, iter) = (expr.pat, expr.expr);
+
+            prelude.push(parse_quote!(let #iter_ident = ::std::iter::IntoIterator::into_iter(#iter);));
+            iteration.push(parse_quote! {
+                let ::std::option::Option::Some(#pat) = ::std::iter::Iterator::next(&mut #iter_ident) else {
+                    break;
+                };
+            });
+            generated_vars.push(LocalVar::generated(iter_ident, true));
+
+            (expr.label, expr.body)
+        }
+        Expr::Loop(expr) => (expr.label, expr.body),
+        _ => unreachable!(),
+    };
+    let label = label.map(|label| label.name);
+
+    // `while` and `for` loops are left through the first statement of the iteration
+    let breaks = !iteration.is_empty() || has_break(&body, label.as_ref());
+
+    // Every iteration ends by starting the next one
+    let mut body_stmts = body.stmts;
+    if let Some(Stmt::Expr(_, semi)) = body_stmts.last_mut() {
+        *semi = Some(Default::default());
+    }
+    let diverges = block::ends_in_jump(&body_stmts);
+    iteration.extend(body_stmts);
+    if !diverges {
+        iteration.push(parse_quote!(continue;));
+    }
+
+    let mut vars_iter = scope::live_vars(&lowered_loop.locals, &scope::free_vars(&iteration));
+    vars_iter.extend(generated_vars);
+    for var in &vars_iter {
+        cx.thread_var(var);
+    }
+
+    let vars_exit: Vec<LocalVar> = if breaks {
+        let used_after = scope::free_vars(&inner_stmts);
+        vars_iter.iter()
+                 .filter(|var| used_after.contains(&var.ident.to_string()))
+                 .cloned()
+                 .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut body_con = con.nested();
+    body_con.locals = vars_iter.clone();
+    body_con.locals.push(LocalVar::generated(loop_ident.clone(), false));
+    body_con.locals.push(LocalVar::generated(break_ident.clone(), false));
+    body_con.loops = lowered_loop.loops;
+    body_con.loops.push(LoopCtx::Lowered(Lowered {
+        label,
+        loop_ident: loop_ident.clone(),
+        break_ident: break_ident.clone(),
+        vars_iter: vars_iter.clone(),
+        vars_exit: vars_exit.clone(),
+    }));
+
+    let iteration = block::convert_stmts(iteration, &body_con);
+
+    let vars_iter_expr = scope::vars_expr(&vars_iter);
+    let vars_iter_pat = scope::vars_pat(&vars_iter);
+
+    // Without a break the statements after the loop are never reached
+    let exit: Expr = if breaks {
+        let vars_exit_pat = scope::vars_pat(&vars_exit);
+        parse_quote! {
+            move |#state_ident| {
+                #[allow(unused_mut, unused_variables)]
+                let (#vars_exit_pat, #param) = #state_ident;
+                #(#inner_stmts)*
+            }
+        }
+    } else {
+        parse_quote!(move |_: ()| {})
+    };
+
+    parse_quote! {
+        {
+            #(#prelude)*
+            __rust_async_autogen_branch(
+                move |#break_ident| {
+                    __rust_async_autogen_loop((#vars_iter_expr, #break_ident), |#state_ident| {
+                        #[allow(unused_mut, unused_variables)]
+                        let ((#vars_iter_pat, #break_ident), #loop_ident) = #state_ident;
+                        #(#iteration)*
+                    })
+                },
+                #exit,
+            )
+        }
+    }
+}
+
+/// Whether a `for` loop iterates over `await_each!(stream)`
+fn is_await_each(iter: &Expr) -> bool {
+    match *iter {
+        Expr::Macro(ref expr) => expr.mac.path.is_ident(AWAIT_EACH_IDENT),
+        _ => false,
+    }
+}
+
+/// `break` leaving a lowered loop hands the variables to the statements after it
+pub fn convert_break(expr: ExprBreak, con: &mut ConversionSess) -> Expr {
+    let value = expr.expr.clone().await_to_cb(con);
+
+    match target(&con.loops, expr.label.as_ref()) {
+        Some(LoopCtx::Lowered(lowered)) => {
+            let vars = scope::vars_expr(&lowered.vars_exit);
+            let break_ident = &lowered.break_ident;
+            let value = match value {
+                Some(value) => *value,
+                None => parse_quote!(()),
+            };
+
+            eval_first(con.cx, value, |value| parse_quote!(return #break_ident((#vars, #value))))
+        }
+        _ => Expr::Break(ExprBreak { expr: value, ..expr }),
+    }
+}
+
+/// `continue` in a lowered loop starts the next iteration
+pub fn convert_continue(expr: ExprContinue, con: &mut ConversionSess) -> Expr {
+    match target(&con.loops, expr.label.as_ref()) {
+        Some(LoopCtx::Lowered(lowered)) => {
+            let vars = scope::vars_expr(&lowered.vars_iter);
+            let (loop_ident, break_ident) = (&lowered.loop_ident, &lowered.break_ident);
+
+            parse_quote!(return __rust_async_autogen_loop((#vars, #break_ident), #loop_ident.0))
+        }
+        _ => Expr::Continue(expr),
+    }
+}
+
+/// The loop a `break` or `continue` refers to
+fn target<'b>(loops: &'b [LoopCtx], label: Option<&Lifetime>) -> Option<&'b LoopCtx> {
+    match label {
+        Some(label) => loops.iter().rev().find(|ctx| ctx.label() == Some(label)),
+        None => loops.last(),
+    }
+}
+
+/// Whether the body of a `loop` can be left with `break`
+fn has_break(body: &Block, label: Option<&Lifetime>) -> bool {
+    struct BreakFinder<'a> {
+        label: Option<&'a Lifetime>,
+        depth: usize,
+        found: bool,
+    }
+
+    impl<'a, 'ast> Visit<'ast> for BreakFinder<'a> {
+        fn visit_expr_break(&mut self, expr: &'ast ExprBreak) {
+            match expr.label {
+                Some(ref label) => self.found |= self.label == Some(label),
+                None => self.found |= self.depth == 0,
+            }
+            visit::visit_expr_break(self, expr);
+        }
+
+        fn visit_expr_while(&mut self, expr: &'ast ExprWhile) {
+            self.depth += 1;
+            visit::visit_expr_while(self, expr);
+            self.depth -= 1;
+        }
+
+        fn visit_expr_for_loop(&mut self, expr: &'ast ExprForLoop) {
+            self.depth += 1;
+            visit::visit_expr_for_loop(self, expr);
+            self.depth -= 1;
+        }
+
+        fn visit_expr_loop(&mut self, expr: &'ast ExprLoop) {
+            self.depth += 1;
+            visit::visit_expr_loop(self, expr);
+            self.depth -= 1;
+        }
+
+        fn visit_expr_closure(&mut self, _: &'ast ExprClosure) {}
+
+        fn visit_item(&mut self, _: &'ast Item) {}
+    }
+
+    let mut finder = BreakFinder { label, depth: 0, found: false };
+    finder.visit_block(body);
+    finder.found
+}
@@ -1,29 +1,317 @@
+use proc_macro2::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+use quote::ToTokens;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashSet};
 use std::iter::Iterator;
 use std::vec::Vec;
-use syntax::ast::*;
-use syntax::ext::base::ExtCtxt;
+use syn::*;
+use syn::visit_mut::{self, VisitMut};
 
 mod stmt;
 mod block;
+mod branch;
 mod expr;
+mod loops;
+mod scope;
+mod select;
+mod stream;
+mod support;
+
+pub use self::block::ends_in_jump;
+pub use self::loops::LoopCtx;
+pub use self::scope::{pat_bindings, LocalVar};
+pub use self::stream::lower_yields;
+pub use self::support::Helper;
+
+/// Name `await` is renamed to while the item is parsed
+pub const AWAIT_IDENT: &str = "__rust_async_await";
+/// Name `join` is renamed to, it awaits several calls at once
+pub const JOIN_IDENT: &str = "__rust_async_join";
+/// Name `select` is renamed to, it continues with the first of several calls to complete
+pub const SELECT_IDENT: &str = "__rust_async_select";
+/// Name `await_each` is renamed to, a `for` loop over it awaits each value of a stream
+pub const AWAIT_EACH_IDENT: &str = "__rust_async_await_each";
+
+/// Name `yield` is renamed to, it hands a value to the consumer of an `#[async_stream]`
+pub const YIELD_IDENT: &str = "__rust_async_yield";
+
+/// Name `self` is renamed to inside the body of a method
+pub const SELF_IDENT: &str = "__rust_async_autogen_self";
+
+/// Callback receiving the return value of the converted function
+pub const FINAL_CB_IDENT: &str = "__rust_async_autogen_final_callback";
+
+/// Settings given to the attribute, like `#[async(trampoline)]`
+#[derive(Default)]
+pub struct Options {
+    /// Continuations are run by `async_runtime::trampoline`,
+    /// so awaits that complete synchronously don't grow the stack
+    pub trampoline: bool,
+    /// The function returns a future instead of taking a final callback
+    pub future: bool,
+    /// Continuations stop if the `CancellationToken` argument was cancelled,
+    /// the final callback receives `Err(Cancelled)` instead
+    pub cancellable: bool,
+    /// The function returns a stream of the values it yields, set by `#[async_stream]`
+    pub stream: bool,
+    /// The final callback has to be `'static`, so continuations holding it
+    /// can wait on the event loop
+    pub static_callback: bool,
+}
+
+impl parse::Parse for Options {
+    fn parse(input: parse::ParseStream) -> Result<Self> {
+        let mut options = Options::default();
+
+        while !input.is_empty() {
+            // `'static` is the only lifetime, every other option is a word
+            if input.peek(Lifetime) {
+                let lifetime: Lifetime = input.parse()?;
+                if lifetime.ident != "static" {
+                    return Err(Error::new(lifetime.span(), format!("unknown async option `{}`", lifetime)));
+                }
+                options.static_callback = true;
+            } else {
+                let option: Ident = input.parse()?;
+                match option.to_string().as_str() {
+                    "trampoline" => options.trampoline = true,
+                    "future" => options.future = true,
+                    "cancellable" => options.cancellable = true,
+                    _ => return Err(Error::new(option.span(), format!("unknown async option `{}`", option))),
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// `compile_error!` for every message of `err`. Unlike `Error::to_compile_error` it doesn't
+/// go through `::core`, which isn't in scope in 2015 edition crates.
+pub fn compile_error(err: Error) -> TokenStream {
+    err.into_iter()
+       .map(|err| {
+           let msg = err.to_string();
+           quote_spanned!(err.span()=> compile_error!(#msg);)
+       })
+       .collect()
+}
+
+/// Collects the errors emitted while converting a single item
+pub struct Context {
+    pub options: Options,
+    /// Argument of a cancellable function holding its `CancellationToken`
+    pub cancellation_token: Option<Ident>,
+    /// The function is declared to return an `Option`, so `?` hands `None` to the final callback
+    pub returns_option: bool,
+    errors: RefCell<Vec<Error>>,
+    helpers: RefCell<BTreeSet<Helper>>,
+    next_id: Cell<usize>,
+    /// Mutable variables moved into the state of a lowered loop
+    threaded_mut: RefCell<HashSet<String>>,
+    /// Whether a generated closure assigns to a variable it captured
+    captured_assignments: Cell<bool>,
+}
+
+impl Context {
+    pub fn new(options: Options) -> Self {
+        Context {
+            options,
+            cancellation_token: None,
+            returns_option: false,
+            errors: RefCell::new(Vec::new()),
+            helpers: RefCell::new(BTreeSet::new()),
+            next_id: Cell::new(0),
+            threaded_mut: RefCell::new(HashSet::new()),
+            captured_assignments: Cell::new(false),
+        }
+    }
+
+    pub fn span_err(&self, span: Span, msg: &str) {
+        self.errors.borrow_mut().push(Error::new(span, msg));
+    }
+
+    pub fn ident_of(&self, name: &str) -> Ident {
+        Ident::new(name, Span::call_site())
+    }
+
+    /// Unique number for naming generated items
+    pub fn next_id(&self) -> usize {
+        let id = self.next_id.get() + 1;
+        self.next_id.set(id);
+        id
+    }
+
+    /// Leaves a cancellable function through its final callback if its token was cancelled
+    pub fn cancellation_check(&self) -> Option<Stmt> {
+        let token = self.cancellation_token.as_ref()?;
+        Some(parse_quote! {
+            if #token.is_cancelled() {
+                return __rust_async_autogen_final_callback(
+                    ::std::result::Result::Err(::async_runtime::Cancelled));
+            }
+        })
+    }
+
+    /// Marks a support item as needed by the generated code
+    pub fn use_helper(&self, helper: Helper) {
+        self.helpers.borrow_mut().insert(helper);
+    }
+
+    /// Support items that have to be placed at the top of the converted function
+    pub fn support_items(&self) -> Vec<Stmt> {
+        support::items(&self.helpers.borrow(), &self.options)
+    }
+
+    /// Remembers that a variable is passed on by a lowered loop. The loop rebinds it
+    /// mutably, so the original binding might not need to be mutable anymore.
+    pub fn thread_var(&self, var: &LocalVar) {
+        if var.mutable && !var.always_live {
+            self.threaded_mut.borrow_mut().insert(var.ident.to_string());
+        }
+    }
+
+    /// Silences `unused_mut` on the bindings of variables passed on by lowered loops
+    pub fn allow_threaded_mut(&self, sig: &mut Signature, block: &mut Block) {
+        scope::allow_unused_mut(&self.threaded_mut.borrow(), sig, block);
+    }
+
+    /// Assigning a captured variable inside a generated closure drops the value that was
+    /// moved into it, which is expected here. Remembers to silence the lint for the function.
+    pub fn check_captured_assignments(&self, body: &[Stmt], locals: &[LocalVar]) {
+        if scope::assigns_local(body, locals) {
+            self.captured_assignments.set(true);
+        }
+    }
+
+    /// Lints the generated code triggers although the original function is fine
+    pub fn allowed_lints(&self) -> Option<Attribute> {
+        if self.captured_assignments.get() {
+            Some(parse_quote!(#[allow(unused_assignments)]))
+        } else {
+            None
+        }
+    }
+
+    pub fn into_compile_errors(self) -> TokenStream {
+        self.errors.into_inner().into_iter().map(compile_error).collect()
+    }
+}
 
 pub struct ConversionSess<'a> {
-    pub cx: &'a ExtCtxt<'a>,
+    pub cx: &'a Context,
     /// Function returns something
     /// if it doesn't return anything we don't give it a final callback
     pub final_cb: bool,
-    pub await_functions: Vec<ExprKind>,
+    /// Points in the current statement that wait for a callback
+    pub suspensions: Vec<Suspension>,
     /// When a callback is triggered every statement
     /// below it needs to go inside the generated closure
     pub remaining_stmts: Vec<Stmt>,
+    /// Variables in scope, lowered loops have to pass them along explicitly
+    pub locals: Vec<LocalVar>,
+    /// Loops surrounding the current statement, innermost last
+    pub loops: Vec<LoopCtx>,
 }
 
 impl<'a> ConversionSess<'a> {
-	pub fn new(cx: &'a ExtCtxt<'a>, final_cb: bool) -> Self {
-		ConversionSess {cx: cx, final_cb: final_cb, await_functions: Vec::new(), remaining_stmts: Vec::new()}
+	pub fn new(cx: &'a Context, final_cb: bool) -> Self {
+		ConversionSess {
+			cx,
+			final_cb,
+			suspensions: Vec::new(),
+			remaining_stmts: Vec::new(),
+			locals: Vec::new(),
+			loops: Vec::new(),
+		}
+	}
+
+	/// Session for a nested block that shares the surrounding scope
+	pub fn nested(&self) -> ConversionSess<'a> {
+		ConversionSess {
+			locals: self.locals.clone(),
+			loops: self.loops.clone(),
+			..ConversionSess::new(self.cx, self.final_cb)
+		}
+	}
+
+	/// Registers a suspension point and returns the expression
+	/// its result will be available under inside the callback
+	pub fn suspend(&mut self, suspension: Suspension) -> Expr {
+		self.suspensions.push(suspension);
+		let var_ident = callback_ident(self.cx, self.suspensions.len());
+
+		parse_quote!(#var_ident)
 	}
 }
 
+/// A point where the rest of the statement has to wait for a callback
+pub enum Suspension {
+    /// `await!(get_user(1))`, the callback is appended to the call's arguments
+    Call(Expr),
+    /// A loop containing awaits, lowered into a recursive continuation
+    Loop(loops::Loop),
+    /// An `if`, `match` or block with awaits in its branches, joined by a shared continuation
+    Branch(branch::Branch),
+}
+
+/// Name of the callback argument holding the result of the n-th suspension in a statement
+pub fn callback_ident(cx: &Context, n: usize) -> Ident {
+    cx.ident_of(&format!("__rust_async_autogen_callback{}", n))
+}
+
+/// Whether the code contains await calls that will turn into callbacks
+pub fn contains_await<T: ToTokens>(node: &T) -> bool {
+    any_token(node.to_token_stream(), &|token| {
+        match *token {
+            TokenTree::Ident(ref ident) => {
+                ident == AWAIT_IDENT || ident == JOIN_IDENT || ident == SELECT_IDENT || ident == AWAIT_EACH_IDENT
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Whether evaluating the expression can leave the function through the final callback (`?`).
+/// Such a value has to be evaluated before the callbacks it is handed to are moved.
+pub fn exits_early(expr: &Expr) -> bool {
+    any_token(expr.to_token_stream(), &|token| {
+        match *token {
+            TokenTree::Ident(ref ident) => ident == FINAL_CB_IDENT,
+            TokenTree::Punct(ref punct) => punct.as_char() == '?',
+            _ => false,
+        }
+    })
+}
+
+/// Evaluates `value` into a variable first if it can exit early, then builds the expression using it
+pub fn eval_first<F>(cx: &Context, value: Expr, build: F) -> Expr
+    where F: FnOnce(&Expr) -> Expr
+{
+    if !exits_early(&value) {
+        return build(&value);
+    }
+
+    let value_ident = cx.ident_of("__rust_async_autogen_value");
+    let expr = build(&parse_quote!(#value_ident));
+    parse_quote!({
+        let #value_ident = #value;
+        #expr
+    })
+}
+
+fn any_token(tokens: TokenStream, pred: &dyn Fn(&TokenTree) -> bool) -> bool {
+    tokens.into_iter().any(|token| {
+        match token {
+            TokenTree::Group(ref group) => any_token(group.stream(), pred),
+            ref token => pred(token),
+        }
+    })
+}
+
 /// Converts await macros contained within itself to callbacks
 pub trait AwaitToCb {
     fn await_to_cb(self, con: &mut ConversionSess) -> Self;
@@ -33,7 +321,7 @@ impl<T> AwaitToCb for Option<T>
     where T: AwaitToCb
 {
     fn await_to_cb(self: Option<T>, con: &mut ConversionSess) -> Option<T> {
-        self.map_or(None, |expr_kind| Some(expr_kind.await_to_cb(con)))
+        self.map(|expr_kind| expr_kind.await_to_cb(con))
     }
 }
 
@@ -44,3 +332,164 @@ impl<T> AwaitToCb for Vec<T>
         self.into_iter().map(|item| item.await_to_cb(con)).collect()
     }
 }
+
+impl<T> AwaitToCb for Box<T>
+    where T: AwaitToCb
+{
+    fn await_to_cb(self, con: &mut ConversionSess) -> Box<T> {
+        Box::new((*self).await_to_cb(con))
+    }
+}
+
+impl<T, P> AwaitToCb for punctuated::Punctuated<T, P>
+    where T: AwaitToCb
+{
+    fn await_to_cb(self, con: &mut ConversionSess) -> Self {
+        self.into_pairs()
+            .map(|pair| {
+                let (item, punct) = pair.into_tuple();
+                punctuated::Pair::new(item.await_to_cb(con), punct)
+            })
+            .collect()
+    }
+}
+
+/// Renames `await!` invocations so the item can be parsed by syn,
+/// which treats `await` as a reserved keyword. `join!`, `select!` and `await_each!` are renamed as well,
+/// so they are found by the same checks, and `r#yield!` so it can be told apart from a function call.
+/// Macros named by a path, like `futures::join!`, are left alone.
+pub fn escape_keywords(tokens: TokenStream) -> TokenStream {
+    rename_macros(tokens, &|name| {
+        match name {
+            "await" => Some(AWAIT_IDENT),
+            "join" => Some(JOIN_IDENT),
+            "select" => Some(SELECT_IDENT),
+            "await_each" => Some(AWAIT_EACH_IDENT),
+            "r#yield" => Some(YIELD_IDENT),
+            _ => None,
+        }
+    })
+}
+
+/// Gives `join!`, `select!` and `await_each!` their names back inside closures and nested items.
+/// Those aren't converted, so the macros refer to whatever macros of that name are in scope.
+pub fn unescape_unconverted(block: &mut Block) {
+    struct Unescape;
+
+    fn unescape<T: ToTokens + parse::Parse>(node: &mut T) {
+        let tokens = rename_macros(node.to_token_stream(), &|name| {
+            match name {
+                JOIN_IDENT => Some("join"),
+                SELECT_IDENT => Some("select"),
+                AWAIT_EACH_IDENT => Some("await_each"),
+                _ => None,
+            }
+        });
+        *node = parse2(tokens).expect("renaming macros keeps the code valid");
+    }
+
+    impl VisitMut for Unescape {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            match *expr {
+                Expr::Closure(_) => unescape(expr),
+                _ => visit_mut::visit_expr_mut(self, expr),
+            }
+        }
+
+        fn visit_item_mut(&mut self, item: &mut Item) {
+            unescape(item);
+        }
+    }
+
+    Unescape.visit_block_mut(block);
+}
+
+/// Renames the macros `rename` returns a new name for, unless they are named by a path
+fn rename_macros(tokens: TokenStream, rename: &dyn Fn(&str) -> Option<&'static str>) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = tokens.into_iter().collect();
+
+    for i in 0..tokens.len() {
+        let in_path = i > 0 && matches!(tokens[i - 1], TokenTree::Punct(ref punct) if punct.as_char() == ':');
+        let replacement = match tokens[i] {
+            TokenTree::Ident(ref ident) if !in_path => {
+                match (rename(&ident.to_string()), tokens.get(i + 1)) {
+                    (Some(renamed), Some(TokenTree::Punct(punct))) if punct.as_char() == '!' => {
+                        Some(TokenTree::Ident(Ident::new(renamed, ident.span())))
+                    }
+                    _ => None,
+                }
+            }
+            TokenTree::Group(ref group) => {
+                let mut renamed = Group::new(group.delimiter(), rename_macros(group.stream(), rename));
+                renamed.set_span(group.span());
+                Some(TokenTree::Group(renamed))
+            }
+            _ => None,
+        };
+
+        if let Some(replacement) = replacement {
+            tokens[i] = replacement;
+        }
+    }
+
+    tokens.into_iter().collect()
+}
+
+/// Renames `self` so the generated code can pass it along like any other variable.
+/// Paths starting with `self::` refer to the module and are kept, so are nested
+/// `fn`, `impl`, `trait` and `mod` items, which have a `self` of their own.
+pub fn escape_self(tokens: TokenStream) -> TokenStream {
+    let mut tokens: Vec<TokenTree> = tokens.into_iter().collect();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        if starts_item(&tokens[i..]) {
+            // The item is kept as it is, up to and including its body
+            while i < tokens.len() {
+                match tokens[i] {
+                    TokenTree::Group(ref group) if group.delimiter() == Delimiter::Brace => break,
+                    TokenTree::Punct(ref punct) if punct.as_char() == ';' => break,
+                    _ => i += 1,
+                }
+            }
+            i += 1;
+            continue;
+        }
+
+        let replacement = match tokens[i] {
+            TokenTree::Ident(ref ident) if ident == "self" => {
+                match tokens.get(i + 1) {
+                    Some(TokenTree::Punct(punct)) if punct.as_char() == ':' => None,
+                    _ => Some(TokenTree::Ident(Ident::new(SELF_IDENT, ident.span()))),
+                }
+            }
+            TokenTree::Group(ref group) => {
+                let mut escaped = Group::new(group.delimiter(), escape_self(group.stream()));
+                escaped.set_span(group.span());
+                Some(TokenTree::Group(escaped))
+            }
+            _ => None,
+        };
+
+        if let Some(replacement) = replacement {
+            tokens[i] = replacement;
+        }
+        i += 1;
+    }
+
+    tokens.into_iter().collect()
+}
+
+/// Whether the tokens start with a `fn`, `impl`, `trait` or `mod` item. `fn` pointer types don't.
+fn starts_item(tokens: &[TokenTree]) -> bool {
+    match (tokens.first(), tokens.get(1)) {
+        (Some(TokenTree::Ident(keyword)), Some(next)) => {
+            match keyword.to_string().as_str() {
+                "fn" | "trait" | "mod" => matches!(*next, TokenTree::Ident(_)),
+                "impl" => true,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
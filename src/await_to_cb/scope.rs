@@ -0,0 +1,409 @@
+use proc_macro2::{Ident, TokenStream, TokenTree};
+use std::collections::HashSet;
+use quote::ToTokens;
+use syn::*;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+use syn::visit_mut::{self, VisitMut};
+
+/// A local variable that is visible at some point of the converted function
+#[derive(Clone)]
+pub struct LocalVar {
+    pub ident: Ident,
+    pub mutable: bool,
+    /// Generated variables (final callback, loop continuations) that have to
+    /// be carried along even though they are not mentioned in the source
+    pub always_live: bool,
+}
+
+impl LocalVar {
+    pub fn new(ident: Ident, mutable: bool) -> Self {
+        LocalVar { ident, mutable, always_live: false }
+    }
+
+    pub fn generated(ident: Ident, mutable: bool) -> Self {
+        LocalVar { ident, mutable, always_live: true }
+    }
+}
+
+/// Collects the variables bound by a pattern
+pub fn pat_bindings(pat: &Pat, bindings: &mut Vec<LocalVar>) {
+    match *pat {
+        Pat::Ident(ref pat_ident) => {
+            // Uppercase identifiers without a binding mode are
+            // most likely unit structs, enum variants or constants
+            let is_binding = pat_ident.by_ref.is_some() || pat_ident.mutability.is_some() ||
+                             pat_ident.subpat.is_some() ||
+                             !pat_ident.ident.to_string().starts_with(char::is_uppercase);
+
+            if is_binding {
+                bindings.push(LocalVar::new(pat_ident.ident.clone(),
+                                            pat_ident.mutability.is_some()));
+            }
+            if let Some((_, ref subpat)) = pat_ident.subpat {
+                pat_bindings(subpat, bindings);
+            }
+        }
+        Pat::Or(ref pat_or) => {
+            // Every alternative binds the same variables
+            if let Some(pat) = pat_or.cases.first() {
+                pat_bindings(pat, bindings);
+            }
+        }
+        Pat::Paren(ref pat_paren) => pat_bindings(&pat_paren.pat, bindings),
+        Pat::Reference(ref pat_ref) => pat_bindings(&pat_ref.pat, bindings),
+        Pat::Slice(ref pat_slice) => {
+            for pat in &pat_slice.elems {
+                pat_bindings(pat, bindings);
+            }
+        }
+        Pat::Struct(ref pat_struct) => {
+            for field in &pat_struct.fields {
+                pat_bindings(&field.pat, bindings);
+            }
+        }
+        Pat::Tuple(ref pat_tuple) => {
+            for pat in &pat_tuple.elems {
+                pat_bindings(pat, bindings);
+            }
+        }
+        Pat::TupleStruct(ref pat_tuple_struct) => {
+            for pat in &pat_tuple_struct.elems {
+                pat_bindings(pat, bindings);
+            }
+        }
+        Pat::Type(ref pat_type) => pat_bindings(&pat_type.pat, bindings),
+        _ => {}
+    }
+}
+
+/// Keeps the variables from `locals` that are either mentioned in `used`
+/// or always have to be carried along. Shadowed variables only appear once.
+pub fn live_vars(locals: &[LocalVar], used: &HashSet<String>) -> Vec<LocalVar> {
+    let mut seen = HashSet::new();
+    let mut live: Vec<LocalVar> = locals.iter()
+                                        .rev()
+                                        .filter(|var| seen.insert(var.ident.to_string()))
+                                        .filter(|var| {
+                                            var.always_live || used.contains(&var.ident.to_string())
+                                        })
+                                        .cloned()
+                                        .collect();
+    live.reverse();
+    live
+}
+
+/// Tuple expression passing the variables along
+pub fn vars_expr(vars: &[LocalVar]) -> Expr {
+    let idents = vars.iter().map(|var| &var.ident);
+    parse_quote!((#(#idents,)*))
+}
+
+/// Tuple pattern binding the variables again, keeping their mutability
+pub fn vars_pat(vars: &[LocalVar]) -> TokenStream {
+    let bindings = vars.iter().map(|var| {
+        let ident = &var.ident;
+        if var.mutable {
+            quote!(mut #ident)
+        } else {
+            quote!(#ident)
+        }
+    });
+    quote!((#(#bindings,)*))
+}
+
+/// Names of the variables used by a piece of code that are not bound inside of it
+pub fn free_vars<T: FreeVars>(node: &T) -> HashSet<String> {
+    let mut visitor = FreeVarsVisitor { scopes: vec![HashSet::new()], free: HashSet::new() };
+    node.visit_free_vars(&mut visitor);
+    visitor.free
+}
+
+pub trait FreeVars {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor);
+}
+
+impl FreeVars for Expr {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor) {
+        visitor.visit_expr(self);
+    }
+}
+
+impl FreeVars for Block {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor) {
+        visitor.visit_block(self);
+    }
+}
+
+impl FreeVars for Stmt {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor) {
+        visitor.visit_stmt(self);
+    }
+}
+
+impl<T: FreeVars> FreeVars for [T] {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor) {
+        for node in self {
+            node.visit_free_vars(visitor);
+        }
+    }
+}
+
+impl<T: FreeVars> FreeVars for Vec<T> {
+    fn visit_free_vars(&self, visitor: &mut FreeVarsVisitor) {
+        self[..].visit_free_vars(visitor);
+    }
+}
+
+pub struct FreeVarsVisitor {
+    scopes: Vec<HashSet<String>>,
+    free: HashSet<String>,
+}
+
+impl FreeVarsVisitor {
+    fn bind(&mut self, pat: &Pat) {
+        let mut bindings = Vec::new();
+        pat_bindings(pat, &mut bindings);
+
+        let scope = self.scopes.last_mut().unwrap();
+        for var in bindings {
+            scope.insert(var.ident.to_string());
+        }
+    }
+
+    fn use_ident(&mut self, ident: &Ident) {
+        self.use_name(ident.to_string());
+    }
+
+    fn use_name(&mut self, name: String) {
+        if !self.scopes.iter().any(|scope| scope.contains(&name)) {
+            self.free.insert(name);
+        }
+    }
+
+    /// Arguments of other macros can't be parsed, so every identifier counts as a use
+    fn use_tokens(&mut self, tokens: TokenStream) {
+        for token in tokens {
+            match token {
+                TokenTree::Ident(ident) => self.use_ident(&ident),
+                TokenTree::Group(group) => self.use_tokens(group.stream()),
+                TokenTree::Literal(literal) => {
+                    if let Ok(lit) = parse2::<LitStr>(literal.into_token_stream()) {
+                        self.use_format_args(&lit);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Variables captured by a format string, like `x` and `width` in `"{x:width$}"`
+    fn use_format_args(&mut self, lit: &LitStr) {
+        let value = lit.value();
+        let mut rest = value.as_str();
+        while let Some(start) = rest.find('{') {
+            rest = &rest[start + 1..];
+            if let Some(escaped) = rest.strip_prefix('{') {
+                rest = escaped;
+                continue;
+            }
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => return,
+            };
+            let (arg, spec) = match rest[..end].find(':') {
+                Some(colon) => (&rest[..colon], &rest[colon + 1..end]),
+                None => (&rest[..end], ""),
+            };
+            // Widths and precisions name their variable right before a `$`
+            let counts = spec.split('$').rev().skip(1).map(|part| {
+                let name: usize = part.chars().rev()
+                                      .take_while(|c| c.is_alphanumeric() || *c == '_')
+                                      .map(char::len_utf8)
+                                      .sum();
+                &part[part.len() - name..]
+            });
+            let names: Vec<String> = counts.chain(Some(arg.trim()))
+                                           .filter(|name| parse_str::<Ident>(name).is_ok())
+                                           .map(String::from)
+                                           .collect();
+            names.into_iter().for_each(|name| self.use_name(name));
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn scoped<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        self.scopes.push(HashSet::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// Visits an `if` / `while` condition, binding `let` patterns for the body
+    fn visit_cond(&mut self, cond: &Expr) {
+        match *cond {
+            Expr::Let(ref expr_let) => {
+                self.visit_expr(&expr_let.expr);
+                self.bind(&expr_let.pat);
+            }
+            Expr::Binary(ref expr) => {
+                self.visit_cond(&expr.left);
+                self.visit_cond(&expr.right);
+            }
+            ref cond => self.visit_expr(cond),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for FreeVarsVisitor {
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.scoped(|visitor| visit::visit_block(visitor, block));
+    }
+
+    fn visit_local(&mut self, local: &'ast Local) {
+        if let Some(ref init) = local.init {
+            self.visit_expr(&init.expr);
+            if let Some((_, ref diverge)) = init.diverge {
+                self.visit_expr(diverge);
+            }
+        }
+        self.bind(&local.pat);
+    }
+
+    fn visit_expr_closure(&mut self, closure: &'ast ExprClosure) {
+        self.scoped(|visitor| {
+            for input in &closure.inputs {
+                visitor.bind(input);
+            }
+            visitor.visit_expr(&closure.body);
+        });
+    }
+
+    fn visit_arm(&mut self, arm: &'ast Arm) {
+        self.scoped(|visitor| {
+            visitor.bind(&arm.pat);
+            if let Some((_, ref guard)) = arm.guard {
+                visitor.visit_expr(guard);
+            }
+            visitor.visit_expr(&arm.body);
+        });
+    }
+
+    fn visit_expr_if(&mut self, expr_if: &'ast ExprIf) {
+        self.scoped(|visitor| {
+            visitor.visit_cond(&expr_if.cond);
+            visitor.visit_block(&expr_if.then_branch);
+        });
+        if let Some((_, ref else_branch)) = expr_if.else_branch {
+            self.visit_expr(else_branch);
+        }
+    }
+
+    fn visit_expr_while(&mut self, expr_while: &'ast ExprWhile) {
+        self.scoped(|visitor| {
+            visitor.visit_cond(&expr_while.cond);
+            visitor.visit_block(&expr_while.body);
+        });
+    }
+
+    fn visit_expr_for_loop(&mut self, expr_for: &'ast ExprForLoop) {
+        self.visit_expr(&expr_for.expr);
+        self.scoped(|visitor| {
+            visitor.bind(&expr_for.pat);
+            visitor.visit_block(&expr_for.body);
+        });
+    }
+
+    fn visit_expr_path(&mut self, expr_path: &'ast ExprPath) {
+        if expr_path.qself.is_none() && expr_path.path.segments.len() == 1 {
+            self.use_ident(&expr_path.path.segments[0].ident);
+        }
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        // Paths in arguments that are expressions aren't mistaken for variables
+        let parser = Punctuated::<Expr, Token![,]>::parse_terminated;
+        match parser.parse2(mac.tokens.clone()) {
+            Ok(exprs) => {
+                for expr in &exprs {
+                    // Inline arguments of format strings are only known to the macro
+                    if let Expr::Lit(ExprLit { lit: Lit::Str(ref lit), .. }) = *expr {
+                        self.use_format_args(lit);
+                    }
+                    self.visit_expr(expr);
+                }
+            }
+            Err(_) => self.use_tokens(mac.tokens.clone()),
+        }
+    }
+
+    fn visit_item(&mut self, _: &'ast Item) {
+        // Items can't refer to local variables
+    }
+}
+
+/// Adds `#[allow(unused_mut)]` to the bindings of the given mutable variables
+pub fn allow_unused_mut(names: &HashSet<String>, sig: &mut Signature, block: &mut Block) {
+    struct AllowUnusedMut<'a> {
+        names: &'a HashSet<String>,
+    }
+
+    impl<'a> AllowUnusedMut<'a> {
+        fn binds_mut(&self, pat: &Pat) -> bool {
+            let mut bindings = Vec::new();
+            pat_bindings(pat, &mut bindings);
+            bindings.iter().any(|var| var.mutable && self.names.contains(&var.ident.to_string()))
+        }
+    }
+
+    impl<'a> VisitMut for AllowUnusedMut<'a> {
+        fn visit_local_mut(&mut self, local: &mut Local) {
+            let allowed = local.attrs.iter().any(|attr| attr.to_token_stream().to_string().contains("unused_mut"));
+            if !allowed && self.binds_mut(&local.pat) {
+                local.attrs.push(parse_quote!(#[allow(unused_mut)]));
+            }
+            visit_mut::visit_local_mut(self, local);
+        }
+
+        fn visit_item_mut(&mut self, _: &mut Item) {}
+    }
+
+    if names.is_empty() {
+        return;
+    }
+
+    let mut visitor = AllowUnusedMut { names };
+    for input in &mut sig.inputs {
+        if let FnArg::Typed(ref mut pat_type) = *input {
+            if visitor.binds_mut(&pat_type.pat) {
+                pat_type.attrs.push(parse_quote!(#[allow(unused_mut)]));
+            }
+        }
+    }
+    visitor.visit_block_mut(block);
+}
+
+/// Whether the statements assign to one of the variables
+pub fn assigns_local(stmts: &[Stmt], locals: &[LocalVar]) -> bool {
+    struct AssignFinder<'a> {
+        locals: &'a [LocalVar],
+        found: bool,
+    }
+
+    impl<'a, 'ast> Visit<'ast> for AssignFinder<'a> {
+        fn visit_expr_assign(&mut self, expr: &'ast ExprAssign) {
+            if let Expr::Path(ref expr_path) = *expr.left {
+                self.found |= self.locals.iter().any(|var| expr_path.path.is_ident(&var.ident));
+            }
+            visit::visit_expr_assign(self, expr);
+        }
+
+        fn visit_item(&mut self, _: &'ast Item) {}
+    }
+
+    let mut finder = AssignFinder { locals, found: false };
+    for stmt in stmts {
+        finder.visit_stmt(stmt);
+    }
+    finder.found
+}
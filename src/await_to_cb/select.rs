@@ -0,0 +1,115 @@
+use super::{expr, support, AwaitToCb, ConversionSess, Helper, Suspension};
+use proc_macro2::Span;
+use syn::*;
+use syn::parse::{Parse, ParseStream};
+
+/// `select! { pat = call => body, .. }`
+struct Select {
+    arms: Vec<SelectArm>,
+}
+
+struct SelectArm {
+    pat: Pat,
+    call: Expr,
+    body: Expr,
+}
+
+impl Parse for Select {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut arms = Vec::new();
+        // Whether the last arm was an expression ending with a comma
+        let mut after_comma = false;
+
+        while !input.is_empty() {
+            let start = input.span();
+            let arm = input.call(parse_arm).map_err(|err| {
+                if after_comma {
+                    Error::new(start, "expected another arm after the comma, \
+                                       a body with commas has to be put in parentheses or a block")
+                } else {
+                    err
+                }
+            })?;
+            after_comma = !matches!(arm.body, Expr::Block(_)) && !input.is_empty();
+            arms.push(arm);
+        }
+
+        Ok(Select { arms })
+    }
+}
+
+/// `pat = call => body` followed by the comma that ends it
+fn parse_arm(input: ParseStream) -> Result<SelectArm> {
+    let pat = Pat::parse_single(input)?;
+    input.parse::<Token![=]>()?;
+    let call = input.call(Expr::parse_without_eager_brace)?;
+    input.parse::<Token![=>]>()?;
+
+    // Like in a match, the comma can be left out after a block. Any other body has to end
+    // with one, so the next arm isn't taken for a part of it
+    let body = if input.peek(token::Brace) {
+        let body = Expr::Block(input.parse()?);
+        input.parse::<Option<Token![,]>>()?;
+        body
+    } else {
+        let body: Expr = input.parse()?;
+        if !input.is_empty() {
+            input.parse::<Token![,]>()
+                 .map_err(|err| Error::new(err.span(), "expected `,` after the body of the arm"))?;
+        }
+        body
+    };
+    Ok(SelectArm { pat, call, body })
+}
+
+/// Starts the calls of every arm and continues with the body of the first one that completes.
+/// The results of the others are ignored, calls after one that completes right away aren't started.
+pub fn lower(mac: ExprMacro, con: &mut ConversionSess, span: Span) -> Expr {
+    let usage = "select macro expects arms of a pattern, a call and a body\n\
+                 like: select! { user = get_user(1) => Some(user), _ = timeout(10) => None }";
+    let arms = match mac.mac.parse_body::<Select>() {
+        Ok(select) if !select.arms.is_empty() => select.arms,
+        Ok(_) => {
+            con.cx.span_err(span, usage);
+            return Expr::Macro(mac);
+        }
+        Err(err) => {
+            con.cx.span_err(err.span(), &format!("{}\n{}", err, usage));
+            return Expr::Macro(mac);
+        }
+    };
+    let select = arms.len();
+    con.cx.use_helper(Helper::Select(select));
+
+    let state_ident = con.cx.ident_of("__rust_async_autogen_select");
+    let enum_ident = support::select_enum_ident(select);
+
+    let mut starts: Vec<Stmt> = Vec::new();
+    let mut match_arms = Vec::new();
+    for (i, arm) in arms.into_iter().enumerate() {
+        let callback = support::callback_ident(i);
+        let call = expr::with_callback(expr::awaited(arm.call.await_to_cb(con), span),
+                                       parse_quote!(#state_ident.#callback()))
+            .expect("awaited expressions are calls");
+        starts.push(parse_quote! {
+            if !#state_ident.is_done() {
+                #call;
+            }
+        });
+
+        let variant = support::select_variant_ident(i);
+        let (pat, body) = (arm.pat, arm.body);
+        match_arms.push(quote!(#enum_ident::#variant(#pat) => #body,));
+    }
+
+    let state = support::select_ident(select);
+    let winner = con.suspend(Suspension::Call(parse_quote_spanned!(span=> ({
+        let #state_ident = #state::new();
+        #(#starts)*
+        #state_ident
+    }).select())));
+
+    // Converted like any other match, so the bodies may await as well
+    let select_match: Expr = parse_quote_spanned!(span=> match #winner { #(#match_arms)* });
+    select_match.await_to_cb(con)
+}
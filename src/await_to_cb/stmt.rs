@@ -1,91 +1,102 @@
-use super::{AwaitToCb, ConversionSess};
-use syntax::ast::*;
-use syntax::ext::build::AstBuilder;
-use syntax::codemap::Spanned;
+use super::{branch, callback_ident, expr, loops, scope, AwaitToCb, ConversionSess, Suspension};
+use syn::*;
+use syn::spanned::Spanned;
 
 impl AwaitToCb for Stmt {
     fn await_to_cb(self, con: &mut ConversionSess) -> Stmt {
-        let stmt_kind = match self.node {
-            StmtKind::Expr(expr, node_id) => StmtKind::Expr(expr.await_to_cb(con), node_id),
-            StmtKind::Semi(expr, node_id) => StmtKind::Semi(expr.await_to_cb(con), node_id),
-            StmtKind::Mac(mac, _, _) => {
-                let expr = con.cx.expr(self.span, ExprKind::Mac((*mac).clone()));
-                let node_id = expr.id;
-                StmtKind::Expr(expr.await_to_cb(con), node_id)
+        let span = self.span();
+
+        let mut stmt = match self {
+            Stmt::Expr(expr, semi) => Stmt::Expr(expr.await_to_cb(con), semi),
+            Stmt::Macro(mac) => {
+                let semi = mac.semi_token;
+                let expr = Expr::Macro(ExprMacro { attrs: mac.attrs, mac: mac.mac });
+                Stmt::Expr(expr.await_to_cb(con), semi)
             }
-            StmtKind::Decl(decl, node_id) => {
-                match decl.node.clone() {
-                    DeclKind::Local(local) => {
-                    	let pat = local.pat.clone();
-                    	
-                    	let stmt = match (local.ty.clone(), local.init.clone().await_to_cb(con)) {
-                    		(None, None) => quote_stmt!(con.cx, let $pat),
-                    		(Some(ty), None) => quote_stmt!(con.cx, let $pat:$ty),
-                    		(None, Some(init)) => quote_stmt!(con.cx, let $pat = $init),
-                    		(Some(ty), Some(init)) => quote_stmt!(con.cx, let $pat:$ty = $init)
-                    	};
-                    	
-                    	stmt.unwrap().node.clone()
+            Stmt::Local(local) => {
+                let init = local.init.map(|init| {
+                    LocalInit {
+                        expr: init.expr.await_to_cb(con),
+                        diverge: init.diverge
+                                     .map(|(else_token, expr)| (else_token, expr.await_to_cb(con))),
+                        ..init
                     }
-                    DeclKind::Item(_) => StmtKind::Decl(decl, node_id),
-                }
+                });
+
+                // Statements below can refer to the new variables
+                scope::pat_bindings(&local.pat, &mut con.locals);
+
+                Stmt::Local(Local { init, ..local })
             }
+            item @ Stmt::Item(_) => item,
         };
 
-        let mut stmt = Spanned {
-            node: stmt_kind,
-            span: self.span,
-        };
+		let num_suspensions = con.suspensions.len();
+		let suspensions: Vec<Suspension> = con.suspensions.drain(..).collect();
 
-		let num_await_functions = con.await_functions.len();
+        for (i, suspension) in suspensions.into_iter().rev().enumerate() {
+            let var_ident = callback_ident(con.cx, num_suspensions - i);
+            let mut param: Pat = parse_quote!(#var_ident);
 
-        for (i, await_function) in con.await_functions.drain(..).rev().enumerate() {
             // Statements that go inside callback closure
             let mut inner_stmts = vec![stmt.clone()];
             // If this is the last callback in the statement we
             // place the remaining statements below inside it
             if i == 0 {
-				let mut con = ConversionSess {
-					remaining_stmts: con.remaining_stmts.drain(..).collect(),
-					await_functions: Vec::new(),
-					cx: con.cx,
-					final_cb: con.final_cb,
-				};
+                // A statement that only consists of the awaited value has no effect
+                if is_path_stmt(&stmt, &var_ident) {
+                    inner_stmts.clear();
+                    param = parse_quote!(_);
+                }
+
+				let mut rest = con.nested();
+				rest.remaining_stmts = con.remaining_stmts.drain(..).collect();
 
 				let mut remaining_stmts = Vec::new();
 
-				while let Some(stmt) = con.remaining_stmts.pop() {
-					remaining_stmts.push(stmt.await_to_cb(&mut con));
+				while let Some(stmt) = rest.remaining_stmts.pop() {
+					remaining_stmts.push(stmt.await_to_cb(&mut rest));
 				}
 
-                inner_stmts.extend_from_slice(&mut remaining_stmts);
+                inner_stmts.extend_from_slice(&remaining_stmts);
             }
-            
-            let var_ident = con.cx.ident_of(&format!("__rust_async_autogen_callback{}", num_await_functions - i));
-
-            // Create callback closure
-            let callback =
-                quote_expr!(con.cx, &mut |$var_ident| {$inner_stmts});
-
-            // Add the callback to the await functions arguments then converted
-            // the function into a statement
-            match await_function {
-                ExprKind::Call(func, mut args) => {
-                    args.push(callback);
-                    stmt = con.cx.stmt_expr(con.cx.expr(self.span, ExprKind::Call(func, args)));
-                }
-                ExprKind::MethodCall(ident, func, mut args) => {
-                    args.push(callback);
-                    stmt = con.cx.stmt_expr(con.cx.expr(self.span,
-                                                        ExprKind::MethodCall(ident, func, args)));
-                }
-                _ => {
-                    con.cx.span_err(self.span,
-                                    "Error creating callbacks - wrong expr kind in await_functions")
+
+            stmt = match suspension {
+                // Add the callback to the await functions arguments then converted
+                // the function into a statement
+                Suspension::Call(await_function) => {
+                    // Create callback closure
+                    // A cancelled function doesn't continue after the await
+                    let check = con.cx.cancellation_check();
+                    let mut callback: Expr = parse_quote!(move |#param| {#check #(#inner_stmts)*});
+                    if con.cx.options.trampoline {
+                        callback = parse_quote!(::async_runtime::trampoline::bounce(#callback));
+                    }
+                    con.cx.check_captured_assignments(&inner_stmts, &con.locals);
+
+                    match expr::with_callback(await_function, callback) {
+                        Some(call) => Stmt::Expr(call, Some(Default::default())),
+                        None => {
+                            con.cx.span_err(span,
+                                            "Error creating callbacks - wrong expr kind in await_functions");
+                            stmt
+                        }
+                    }
                 }
-            }
+                Suspension::Loop(lowered_loop) => loops::lower(lowered_loop, con, param, inner_stmts),
+                Suspension::Branch(branch) => branch::lower(branch, con, param, inner_stmts),
+            };
         }
 
         stmt
     }
 }
+
+/// Checks whether the statement is just `ident;`
+fn is_path_stmt(stmt: &Stmt, ident: &proc_macro2::Ident) -> bool {
+    match *stmt {
+        Stmt::Expr(Expr::Path(ref expr_path), _) => expr_path.path.is_ident(ident),
+        _ => false,
+    }
+}
+
@@ -0,0 +1,82 @@
+use super::{Context, FINAL_CB_IDENT, YIELD_IDENT};
+use proc_macro2::Span;
+use syn::*;
+use syn::spanned::Spanned;
+use syn::visit_mut::{self, VisitMut};
+
+/// Turns `r#yield!(value)` into an await handing the value to the consumer of the stream.
+/// The await completes with the callback receiving the step after it, which takes the place
+/// of the final callback, so the generated code passes it along like any other variable.
+/// Outside of `#[async_stream]` functions `r#yield!` is an error.
+pub fn lower_yields(block: &mut Block, cx: &Context) {
+    let mut lowering = Lowering { cx, stream: cx.options.stream };
+    lowering.visit_block_mut(block);
+}
+
+struct Lowering<'a> {
+    cx: &'a Context,
+    stream: bool,
+}
+
+impl<'a> Lowering<'a> {
+    /// The value of a `r#yield!` macro, `None` if it isn't one
+    fn yielded(&self, mac: &Macro) -> Option<Expr> {
+        if !mac.path.is_ident(YIELD_IDENT) {
+            return None;
+        }
+        if !self.stream {
+            self.cx.span_err(mac.span(), "yield can only be used in #[async_stream] functions");
+            return None;
+        }
+        match mac.parse_body::<Expr>() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.cx.span_err(mac.span(), "yield macro expects the value to hand to the consumer\n\
+                                              like: r#yield!(page)");
+                None
+            }
+        }
+    }
+
+    fn rebind(&self, value: Expr, span: Span) -> Stmt {
+        let emit = self.cx.ident_of(FINAL_CB_IDENT);
+        parse_quote_spanned!(span=> let #emit = __rust_async_await!(::async_runtime::stream::yield_item(#value, #emit));)
+    }
+}
+
+impl<'a> VisitMut for Lowering<'a> {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        let yielded = match *stmt {
+            Stmt::Macro(ref mac) => self.yielded(&mac.mac).map(|value| (value, mac.span())),
+            Stmt::Expr(Expr::Macro(ref expr), Some(_)) => self.yielded(&expr.mac).map(|value| (value, expr.span())),
+            _ => None,
+        };
+
+        match yielded {
+            // The callback is rebound for the statements after the yield
+            Some((value, span)) => *stmt = self.rebind(value, span),
+            None => visit_mut::visit_stmt_mut(self, stmt),
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        let yielded = match *expr {
+            Expr::Macro(ref expr) => self.yielded(&expr.mac).map(|value| (value, expr.span())),
+            _ => None,
+        };
+
+        match yielded {
+            // Like a `match` arm, waiting blocks pass the rebound callback on once they end
+            Some((value, span)) => {
+                let stmt = self.rebind(value, span);
+                *expr = parse_quote!({ #stmt });
+            }
+            None => visit_mut::visit_expr_mut(self, expr),
+        }
+    }
+
+    // Closures and items are checked on their own
+    fn visit_expr_closure_mut(&mut self, _: &mut ExprClosure) {}
+
+    fn visit_item_mut(&mut self, _: &mut Item) {}
+}
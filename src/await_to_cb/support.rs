@@ -0,0 +1,259 @@
+use super::Options;
+use proc_macro2::{Ident, Span};
+use std::collections::BTreeSet;
+use syn::{ImplItem, Index, Stmt};
+
+/// Items the generated code relies on. They are emitted at the top of every
+/// converted function that needs them, so the expansion stays self-contained.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Helper {
+    /// Hands a continuation to code that is written before the continuation,
+    /// which lets the compiler infer the types of the continuation's arguments
+    Branch,
+    /// Runs the iterations of a lowered loop
+    Loop,
+    /// Copies the bindings of a guard that awaits out of the matched value, if they are `Copy`
+    Guard,
+    /// Starts the given number of calls and collects their results
+    Join(usize),
+    /// Starts the given number of calls and continues with the first result
+    Select(usize),
+}
+
+/// State shared by the calls of a `join!`
+pub fn join_ident(calls: usize) -> Ident {
+    Ident::new(&format!("__RustAsyncAutogenJoin{}", calls), Span::call_site())
+}
+
+/// State shared by the calls of a `select!`
+pub fn select_ident(calls: usize) -> Ident {
+    Ident::new(&format!("__RustAsyncAutogenSelectState{}", calls), Span::call_site())
+}
+
+/// Method creating the callback of the call at position `i`
+pub fn callback_ident(i: usize) -> Ident {
+    Ident::new(&format!("callback{}", i), Span::call_site())
+}
+
+fn numbered(prefix: &str, count: usize) -> Vec<Ident> {
+    (0..count).map(|i| Ident::new(&format!("{}{}", prefix, i), Span::call_site())).collect()
+}
+
+/// Enum holding the result of the call that completed first
+pub fn select_enum_ident(calls: usize) -> Ident {
+    Ident::new(&format!("__RustAsyncAutogenSelect{}", calls), Span::call_site())
+}
+
+pub fn select_variant_ident(i: usize) -> Ident {
+    Ident::new(&format!("Arm{}", i), Span::call_site())
+}
+
+pub fn items(helpers: &BTreeSet<Helper>, options: &Options) -> Vec<Stmt> {
+    let mut stmts = Vec::new();
+
+    for helper in helpers {
+        match *helper {
+            Helper::Branch => {
+                stmts.push(parse_quote! {
+                    fn __rust_async_autogen_branch<'a, T, B, K>(branch: B, join: K)
+                        where B: FnOnce(Box<dyn FnOnce(T) + 'a>),
+                              K: FnOnce(T) + 'a
+                    {
+                        branch(Box::new(join))
+                    }
+                });
+            }
+            Helper::Loop => {
+                stmts.push(parse_quote! {
+                    struct __RustAsyncAutogenLoop<S>(fn((S, __RustAsyncAutogenLoop<S>)));
+                });
+                stmts.push(parse_quote! {
+                    impl<S> Clone for __RustAsyncAutogenLoop<S> {
+                        fn clone(&self) -> Self {
+                            *self
+                        }
+                    }
+                });
+                stmts.push(parse_quote! {
+                    impl<S> Copy for __RustAsyncAutogenLoop<S> {}
+                });
+                if options.trampoline {
+                    // Iterations that don't wait for anything would otherwise call each other
+                    stmts.push(parse_quote! {
+                        fn __rust_async_autogen_loop<S: 'static>(state: S, iteration: fn((S, __RustAsyncAutogenLoop<S>))) {
+                            ::async_runtime::trampoline::bounce(move |state| {
+                                iteration((state, __RustAsyncAutogenLoop(iteration)))
+                            })(state)
+                        }
+                    });
+                } else {
+                    stmts.push(parse_quote! {
+                        fn __rust_async_autogen_loop<S>(state: S, iteration: fn((S, __RustAsyncAutogenLoop<S>))) {
+                            iteration((state, __RustAsyncAutogenLoop(iteration)))
+                        }
+                    });
+                }
+            }
+            Helper::Guard => {
+                stmts.push(parse_quote! {
+                    struct __RustAsyncAutogenGuard<'a, T: ?Sized + 'a>(&'a T);
+                });
+                // Inherent methods are tried before trait methods, the trait is only
+                // used for values that aren't `Copy`
+                stmts.push(parse_quote! {
+                    impl<'a, T: Copy + 'a> __RustAsyncAutogenGuard<'a, T> {
+                        #[allow(dead_code)]
+                        fn value(&self) -> T {
+                            *self.0
+                        }
+                    }
+                });
+                stmts.push(parse_quote! {
+                    trait __RustAsyncAutogenGuardRef<'a, T: ?Sized + 'a> {
+                        fn value(&self) -> &'a T;
+                    }
+                });
+                stmts.push(parse_quote! {
+                    impl<'a, T: ?Sized + 'a> __RustAsyncAutogenGuardRef<'a, T> for __RustAsyncAutogenGuard<'a, T> {
+                        fn value(&self) -> &'a T {
+                            self.0
+                        }
+                    }
+                });
+            }
+            Helper::Join(calls) => {
+                let ident = join_ident(calls);
+                let values = numbered("T", calls);
+                let indices: Vec<Index> = (0..calls).map(Index::from).collect();
+
+                let callbacks = (0..calls).map(|i| -> ImplItem {
+                    let (callback, value, index) = (callback_ident(i), &values[i], &indices[i]);
+                    parse_quote! {
+                        fn #callback(&self) -> Box<dyn FnOnce(#value) + 'a> {
+                            let (slots, join) = (self.slots.clone(), self.join.clone());
+                            Box::new(move |value| {
+                                slots.borrow_mut().#index = Some(value);
+                                Self::finish(&slots, &join);
+                            })
+                        }
+                    }
+                });
+
+                stmts.push(parse_quote! {
+                    struct #ident<'a, #(#values),*> {
+                        slots: ::std::rc::Rc<::std::cell::RefCell<(#(Option<#values>,)*)>>,
+                        join: ::std::rc::Rc<::std::cell::RefCell<Option<Box<dyn FnOnce((#(#values,)*)) + 'a>>>>,
+                    }
+                });
+                // Continues once every call completed and the continuation was handed over
+                stmts.push(parse_quote! {
+                    impl<'a, #(#values: 'a),*> #ident<'a, #(#values),*> {
+                        fn new() -> Self {
+                            #ident {
+                                slots: ::std::rc::Rc::new(::std::cell::RefCell::new((#(None::<#values>,)*))),
+                                join: ::std::rc::Rc::new(::std::cell::RefCell::new(None)),
+                            }
+                        }
+
+                        #(#callbacks)*
+
+                        fn join<K: FnOnce((#(#values,)*)) + 'a>(self, join: K) {
+                            *self.join.borrow_mut() = Some(Box::new(join));
+                            Self::finish(&self.slots, &self.join);
+                        }
+
+                        fn finish(slots: &::std::rc::Rc<::std::cell::RefCell<(#(Option<#values>,)*)>>,
+                                  join: &::std::rc::Rc<::std::cell::RefCell<Option<Box<dyn FnOnce((#(#values,)*)) + 'a>>>>) {
+                            let values = {
+                                let mut slots = slots.borrow_mut();
+                                if join.borrow().is_some() && #(slots.#indices.is_some())&&* {
+                                    Some((#(slots.#indices.take().unwrap(),)*))
+                                } else {
+                                    None
+                                }
+                            };
+                            if let Some(values) = values {
+                                let join = join.borrow_mut().take().unwrap();
+                                join(values);
+                            }
+                        }
+                    }
+                });
+            }
+            Helper::Select(calls) => {
+                let ident = select_ident(calls);
+                let enum_ident = select_enum_ident(calls);
+                let values = numbered("T", calls);
+                let variants: Vec<Ident> = (0..calls).map(select_variant_ident).collect();
+
+                let callbacks = (0..calls).map(|i| -> ImplItem {
+                    let (callback, value, variant) = (callback_ident(i), &values[i], &variants[i]);
+                    parse_quote! {
+                        fn #callback(&self) -> Box<dyn FnOnce(#value) + 'a> {
+                            let (winner, select) = (self.winner.clone(), self.select.clone());
+                            Box::new(move |value| {
+                                if winner.borrow().is_some() {
+                                    return;
+                                }
+                                *winner.borrow_mut() = Some(Some(#enum_ident::#variant(value)));
+                                Self::finish(&winner, &select);
+                            })
+                        }
+                    }
+                });
+
+                stmts.push(parse_quote! {
+                    enum #enum_ident<#(#values),*> {
+                        #(#variants(#values)),*
+                    }
+                });
+                stmts.push(parse_quote! {
+                    struct #ident<'a, #(#values),*> {
+                        /// Result of the first call to complete, taken once the continuation runs
+                        winner: ::std::rc::Rc<::std::cell::RefCell<Option<Option<#enum_ident<#(#values),*>>>>>,
+                        select: ::std::rc::Rc<::std::cell::RefCell<Option<Box<dyn FnOnce(#enum_ident<#(#values),*>) + 'a>>>>,
+                    }
+                });
+                // Whoever comes first gets the continuation, the others find the winner already set
+                stmts.push(parse_quote! {
+                    impl<'a, #(#values: 'a),*> #ident<'a, #(#values),*> {
+                        fn new() -> Self {
+                            #ident {
+                                winner: ::std::rc::Rc::new(::std::cell::RefCell::new(None)),
+                                select: ::std::rc::Rc::new(::std::cell::RefCell::new(None)),
+                            }
+                        }
+
+                        fn is_done(&self) -> bool {
+                            self.winner.borrow().is_some()
+                        }
+
+                        #(#callbacks)*
+
+                        fn select<K: FnOnce(#enum_ident<#(#values),*>) + 'a>(self, select: K) {
+                            *self.select.borrow_mut() = Some(Box::new(select));
+                            Self::finish(&self.winner, &self.select);
+                        }
+
+                        fn finish(winner: &::std::rc::Rc<::std::cell::RefCell<Option<Option<#enum_ident<#(#values),*>>>>>,
+                                  select: &::std::rc::Rc<::std::cell::RefCell<Option<Box<dyn FnOnce(#enum_ident<#(#values),*>) + 'a>>>>) {
+                            if select.borrow().is_none() {
+                                return;
+                            }
+                            let value = match *winner.borrow_mut() {
+                                Some(ref mut value) => value.take(),
+                                None => None,
+                            };
+                            if let Some(value) = value {
+                                let select = select.borrow_mut().take().unwrap();
+                                select(value);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    stmts
+}
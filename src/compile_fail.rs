@@ -0,0 +1,43 @@
+//! Code the attribute rejects with an error, checked by the doc tests
+
+/// Const blocks are evaluated at compile time, they can't wait for callbacks
+///
+/// ```compile_fail
+/// extern crate async;
+/// use async::async;
+///
+/// #[async]
+/// fn add_one(i: i32) -> i32 {
+///     i + 1
+/// }
+///
+/// #[async]
+/// fn in_const() -> i32 {
+///     const { await!(add_one(1)) }
+/// }
+/// # fn main() {}
+/// ```
+#[allow(dead_code)]
+struct AwaitInConst;
+
+/// A `select!` arm body that isn't a block ends at the first comma, `b` would start another arm
+///
+/// ```compile_fail
+/// extern crate async;
+/// use async::async;
+///
+/// #[async]
+/// fn add_one(i: i32) -> i32 {
+///     i + 1
+/// }
+///
+/// #[async]
+/// fn pick(a: i32, b: i32) -> (i32, i32) {
+///     select! {
+///         _ = add_one(1) => a, b
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[allow(dead_code)]
+struct SelectArmWithComma;
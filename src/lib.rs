@@ -1,16 +1,16 @@
 //! Automatically generates callbacks under the hood
 //! allowing for async-await style asynchronous programming
 //!
-//! ```rust
+//! ```rust,ignore
 //! #[async]
 //! fn get_user_id() -> Future<User> {
-//! 		let user = await!(db.query("SELECT .."));
-//! 		user.id
+//!     let user = await!(db.query("SELECT .."));
+//!     user.id
 //! }
 //!
 //! #[async]
 //! fn print_id() {
-//! 		println!("user id: {}", await!(get_user_id()));
+//!     println!("user id: {}", await!(get_user_id()));
 //! }
 //!
 //! ```
@@ -18,80 +18,331 @@
 //! Under the hood
 //!
 
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
 
-#![feature(quote, plugin_registrar, rustc_private, plugin, custom_attribute, advanced_slice_patterns, slice_patterns, box_patterns)]
-#![crate_type = "dylib"]
-// #![feature(box_syntax)]
-
-extern crate syntax;
-extern crate rustc_plugin;
-
-use rustc_plugin::Registry;
-use std::boxed::Box;
-use syntax::ast::*;
-use syntax::codemap::Span;
-use syntax::ext::base::{Annotatable, ExtCtxt, SyntaxExtension};
-use syntax::ext::build::AstBuilder;
-use syntax::ext::quote::rt::ToTokens;
-use syntax::parse::token;
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenTree};
+use quote::ToTokens;
+use syn::{Attribute, Block, FnArg, Ident, Pat, ReturnType, Signature, Stmt, Type, Visibility};
+use syn::parse::{Parse, ParseStream};
 
 mod await_to_cb;
+#[cfg(doctest)]
+mod compile_fail;
 
 use await_to_cb::AwaitToCb;
-use await_to_cb::ConversionSess;
+use await_to_cb::{Context, ConversionSess, LocalVar, Options};
 
-#[plugin_registrar]
-pub fn registrar(reg: &mut Registry) {
-    reg.register_syntax_extension(token::intern("async"),
-                                  SyntaxExtension::MultiModifier(Box::new(async_attribute)));
+/// Marking a function with this attribute allows await calls within it to be processed
+///
+/// `join!(a(), b())` inside of the function starts all of the calls at once
+/// and evaluates to a tuple of their results. `select! { pat = call => body, .. }`
+/// starts them as well, but only runs the body of the first one to complete.
+/// `for value in await_each!(stream) { .. }` runs the body for every value of an
+/// `async_runtime::stream::AsyncStream`, continuing after the loop once the stream ended.
+/// Inside closures and nested items, or when named by a path, these are regular macros.
+///
+/// `#[async('static)]` requires the final callback to be `'static`, which continuations
+/// that wait on the `async_runtime` event loop need. Without it the callback may borrow.
+///
+/// `#[async(trampoline)]` hands the continuations to `async_runtime::trampoline`,
+/// so awaits that complete synchronously don't grow the stack. The crate using it
+/// has to depend on `async_runtime`.
+///
+/// `#[async(future)]` turns the function into one returning `impl Future`,
+/// which runs the converted body once it is polled.
+///
+/// `#[async(cancellable)]` makes the function stop once its `CancellationToken` argument
+/// is cancelled. The token is checked when the function is called and whenever it continues
+/// after an await, the final callback then receives `Err(Cancelled)`. Otherwise it receives
+/// `Ok` with the value of the function. A `&CancellationToken` is cloned when the function
+/// is called. The crate using it has to depend on `async_runtime`.
+///
+/// `await!` on anything other than a function call awaits it as a `Future`,
+/// polled by the `async_runtime` event loop.
+#[proc_macro_attribute]
+pub fn async(args: TokenStream, item: TokenStream) -> TokenStream {
+    async_attribute(args.into(), item.into(), false).into()
 }
 
-/// Marking a function with this attribute allows await calls within it to be processed
-fn async_attribute(cx: &mut ExtCtxt,
-                   span: Span,
-                   _: &MetaItem,
-                   annotable: Annotatable)
-                   -> Annotatable {
-
-    // The function item
-    let item = annotable.clone().expect_item();
-
-    // We cannot simply modify the function item
-    // the item, and several of its substructures are wrapped in syntax pointers (syntax::ptr::P)
-    // structs wrapped in these pointers need to be recreated by the AstBuilder
-    if let ItemKind::Fn(dec, unsafety, constness, abi, generics, block) = item.node
-                                                                              .clone() {
-
-		let stmt = quote_stmt!(cx, let (a, b) = (await!(simple_return()), await!(simple_return())););
-		println!("{:?}", stmt.unwrap());
-        // Get function return type
-        let (final_cb, inputs) = match dec.output.clone() {
-            FunctionRetTy::Ty(ty) => {
-                // Recreate the function declaration with an additional callback as an input
-                // and a return type of ()
-                let mut inputs = dec.inputs.clone();
-                inputs.push(quote_arg!(cx, __rust_async_autogen_final_callback: &Fn($ty)));
-
-                (true, inputs)
+/// Turns the function into one returning an `async_runtime::stream::AsyncStream`
+/// of values of the type it is declared to return.
+///
+/// `r#yield!(value)` hands a value to the consumer of the stream. The function continues
+/// once the consumer asks for the next one. It can `await!` like any `#[async]` function,
+/// the stream ends once it returns. `return value` and errors returned with `?`
+/// are yielded as the last value. The raw identifier is needed because the compiler
+/// rejects `yield!` before the attribute gets to see it.
+///
+/// ```rust,ignore
+/// #[async_stream]
+/// fn countdown(from: u32) -> u32 {
+///     for i in (1..=from).rev() {
+///         await!(async_runtime::sleep(Duration::from_secs(1)));
+///         r#yield!(i);
+///     }
+/// }
+/// ```
+///
+/// Options like `#[async_stream(trampoline)]` are the same as those of `#[async]`,
+/// except for `future` and `cancellable`.
+#[proc_macro_attribute]
+pub fn async_stream(args: TokenStream, item: TokenStream) -> TokenStream {
+    async_attribute(args.into(), item.into(), true).into()
+}
+
+fn async_attribute(args: proc_macro2::TokenStream,
+                   item: proc_macro2::TokenStream,
+                   stream: bool)
+                   -> proc_macro2::TokenStream {
+    let mut options = match syn::parse2::<Options>(args) {
+        Ok(options) => options,
+        Err(err) => {
+            let error = await_to_cb::compile_error(err);
+            return quote!(#item #error);
+        }
+    };
+    if stream && (options.future || options.cancellable) {
+        let error = await_to_cb::compile_error(syn::Error::new(Span::call_site(),
+                                                              "streams can't be futures or cancellable"));
+        return quote!(#item #error);
+    }
+    options.stream = stream;
+    let mut cx = Context::new(options);
+
+    // `await` is a reserved keyword for syn, so it is escaped before the item is parsed
+    let async_fn = match syn::parse2::<AsyncFn>(await_to_cb::escape_keywords(item.clone())) {
+        Ok(async_fn) => async_fn,
+        Err(_) => {
+            cx.span_err(Span::call_site(),
+                        "The async annotation only works on functions.");
+            let errors = cx.into_compile_errors();
+            return quote!(#item #errors);
+        }
+    };
+
+    let AsyncFn { mut attrs, vis, mut sig, block } = async_fn;
+
+    // Get function return type
+    let ret_ty = match sig.output.clone() {
+        ReturnType::Type(_, ty) => Some(*ty),
+        ReturnType::Default => None,
+    };
+    if cx.options.stream && ret_ty.is_none() {
+        cx.span_err(sig.ident.span(), "streams need a return type, the type of the values they yield");
+    }
+    // Futures and cancellable functions hand the value of the body to a callback as well, even if it is ()
+    let final_cb = ret_ty.is_some() || cx.options.future || cx.options.cancellable || cx.options.stream;
+    let unit_ret = ret_ty.as_ref().is_none_or(|ty| matches!(*ty, Type::Tuple(ref tuple) if tuple.elems.is_empty()));
+    let mut ret_ty = ret_ty.unwrap_or_else(|| parse_quote!(()));
+    cx.returns_option = matches!(ret_ty, Type::Path(ref path) if path.qself.is_none()
+                                 && path.path.segments.last().is_some_and(|segment| segment.ident == "Option"));
+
+    // A borrowed token is cloned when the function is called, so the continuations own it
+    let mut borrowed_token = None;
+    if cx.options.cancellable {
+        match cancellation_token(&sig) {
+            Some((token, borrowed)) => {
+                if borrowed {
+                    borrowed_token = Some(token.clone());
+                }
+                cx.cancellation_token = Some(token);
             }
-            _ => (false, dec.inputs.clone()),
+            None => cx.span_err(sig.ident.span(),
+                                "cancellable functions need an argument of type `CancellationToken`"),
+        }
+        ret_ty = parse_quote!(::std::result::Result<#ret_ty, ::async_runtime::Cancelled>);
+    }
+
+    if cx.options.stream {
+        // The body runs once the first value is requested, the callback of each step takes the final one's place
+        sig.output = parse_quote!(-> ::async_runtime::stream::AsyncStream<#ret_ty>);
+    } else if cx.options.future {
+        // The function returns a future running the converted body, borrowing what the arguments borrow
+        sig.output = if borrows_args(&sig) {
+            parse_quote!(-> impl ::std::future::Future<Output = #ret_ty> + '_)
+        } else {
+            parse_quote!(-> impl ::std::future::Future<Output = #ret_ty>)
         };
+    } else {
+        if final_cb {
+            // Recreate the function declaration with an additional callback as an input
+            // and a return type of ()
+            // It is taken by value and called once, so continuations can move it along
+            // together with the variables they capture
+            // Continuations waiting on the event loop have to be `'static`, which the function
+            // asks of the callback with `#[async('static)]`. Otherwise the callback may borrow.
+            let arg: FnArg = if cx.options.static_callback {
+                parse_quote!(__rust_async_autogen_final_callback: impl FnOnce(#ret_ty) + 'static)
+            } else {
+                parse_quote!(__rust_async_autogen_final_callback: impl FnOnce(#ret_ty))
+            };
+            sig.inputs.push(arg);
+        }
+
+        sig.output = ReturnType::Default;
+    }
+
+    // Methods declared in traits only get the new signature
+    let block = match block {
+        Some(block) => block,
+        None => return quote!(#(#attrs)* #vis #sig;),
+    };
 
-        let dec = cx.fn_decl(inputs, quote_ty!(cx, ()));
-        // Recreate the function with the new declaration and a modified block
-        let item_fn = ItemKind::Fn(dec,
-                                   unsafety,
-                                   constness,
-                                   abi,
-                                   generics,
-                                   block.await_to_cb(&mut ConversionSess::new(cx, final_cb)));
-
-        Annotatable::Item(cx.item(item.span.clone(),
-                                  item.ident.clone(),
-                                  item.attrs.clone(),
-                                  item_fn))
+    let mut con = ConversionSess::new(&cx, final_cb);
+    let mut prelude = Vec::new();
+    for input in &sig.inputs {
+        match *input {
+            FnArg::Receiver(ref receiver) => {
+                // `self` can't be rebound by the generated code, so it is renamed inside the body
+                let self_ident = cx.ident_of(await_to_cb::SELF_IDENT);
+                let mutability = receiver.mutability;
+                prelude.push(parse_quote!(let #mutability #self_ident = self;));
+                con.locals.push(LocalVar::new(self_ident, mutability.is_some()));
+            }
+            FnArg::Typed(ref pat_type) => await_to_cb::pat_bindings(&pat_type.pat, &mut con.locals),
+        }
+    }
+    if final_cb {
+        let final_cb_ident = cx.ident_of(await_to_cb::FINAL_CB_IDENT);
+        con.locals.push(LocalVar::generated(final_cb_ident, false));
+    }
+    // Every continuation checks the token, so lowered loops carry it along like the final callback
+    if let Some(ref token) = cx.cancellation_token {
+        for var in con.locals.iter_mut().filter(|var| var.ident == *token) {
+            var.always_live = true;
+        }
+    }
+
+    let block = if prelude.is_empty() {
+        block
     } else {
-        cx.span_err(span, "The async annotation only works on functions.");
-        annotable
+        let tokens = await_to_cb::escape_self(block.to_token_stream());
+        syn::parse2(tokens).expect("renaming self keeps the block valid")
+    };
+
+    let mut block = block;
+    await_to_cb::unescape_unconverted(&mut block);
+    await_to_cb::lower_yields(&mut block, &cx);
+
+    // The values of streams are yielded, the end of the body only ends the stream
+    if cx.options.stream {
+        if let Some(Stmt::Expr(_, ref mut semi @ None)) = block.stmts.last_mut() {
+            *semi = Some(Default::default());
+        }
+    }
+
+    // The final callback also has to be called when the end of a function returning () is reached
+    let has_tail = matches!(block.stmts.last(), Some(Stmt::Expr(_, None)));
+    if final_cb && (unit_ret || cx.options.stream) && !has_tail && !await_to_cb::ends_in_jump(&block.stmts) {
+        block.stmts.push(parse_quote!(return;));
     }
-}
\ No newline at end of file
+
+    // Recreate the function with the new declaration and a modified block
+    let mut block = block.await_to_cb(&mut con);
+    // A function that is cancelled before it is called doesn't start
+    block.stmts.splice(0..0, cx.cancellation_check());
+    if let Some(ref token) = borrowed_token {
+        block.stmts.insert(0, parse_quote!(let #token = ::std::clone::Clone::clone(#token);));
+    }
+    block.stmts.splice(0..0, prelude);
+    block.stmts.splice(0..0, cx.support_items());
+    cx.allow_threaded_mut(&mut sig, &mut block);
+    attrs.extend(cx.allowed_lints());
+    let body = if cx.options.stream {
+        let final_cb_ident = cx.ident_of(await_to_cb::FINAL_CB_IDENT);
+        quote!({
+            ::async_runtime::stream::AsyncStream::new(move |#final_cb_ident: ::async_runtime::stream::Emit<#ret_ty>| #block)
+        })
+    } else if cx.options.future {
+        let final_cb_ident = cx.ident_of(await_to_cb::FINAL_CB_IDENT);
+        quote!({
+            ::async_runtime::future::AsyncFn::new(move |#final_cb_ident: Box<dyn FnOnce(#ret_ty)>| #block)
+        })
+    } else {
+        quote!(#block)
+    };
+    let errors = cx.into_compile_errors();
+
+    quote! {
+        #(#attrs)*
+        #vis #sig #body
+        #errors
+    }
+}
+
+/// Whether the arguments of the function can hold references
+fn borrows_args(sig: &Signature) -> bool {
+    fn borrows(tokens: proc_macro2::TokenStream) -> bool {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        let is_static = |i: usize| {
+            match (tokens.get(i), tokens.get(i + 1)) {
+                (Some(TokenTree::Punct(punct)), Some(TokenTree::Ident(ident))) => {
+                    punct.as_char() == '\'' && ident == "static"
+                }
+                _ => false,
+            }
+        };
+
+        tokens.iter().enumerate().any(|(i, token)| {
+            match *token {
+                // References other than &'static
+                TokenTree::Punct(ref punct) if punct.as_char() == '&' => !is_static(i + 1),
+                TokenTree::Punct(ref punct) if punct.as_char() == '\'' => !is_static(i),
+                TokenTree::Group(ref group) => borrows(group.stream()),
+                _ => false,
+            }
+        })
+    }
+
+    borrows(sig.inputs.to_token_stream())
+}
+
+/// Name of the argument whose type is `CancellationToken`, and whether it is a reference
+fn cancellation_token(sig: &Signature) -> Option<(Ident, bool)> {
+    sig.inputs.iter().find_map(|input| {
+        let pat_type = match *input {
+            FnArg::Typed(ref pat_type) => pat_type,
+            FnArg::Receiver(_) => return None,
+        };
+        let (ty, borrowed) = match *pat_type.ty {
+            Type::Reference(ref reference) => (&*reference.elem, true),
+            ref ty => (ty, false),
+        };
+        match (&*pat_type.pat, ty) {
+            (Pat::Ident(pat), Type::Path(path)) if path.path.segments.last()?.ident == "CancellationToken" => {
+                Some((pat.ident.clone(), borrowed))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// A function or method marked as async. Methods declared in traits have no body.
+struct AsyncFn {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    sig: Signature,
+    block: Option<Block>,
+}
+
+impl Parse for AsyncFn {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let sig = input.parse()?;
+        let block = if input.peek(Token![;]) {
+            input.parse::<Token![;]>()?;
+            None
+        } else {
+            Some(input.parse()?)
+        };
+
+        Ok(AsyncFn { attrs, vis, sig, block })
+    }
+}
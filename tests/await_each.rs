@@ -0,0 +1,120 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::{async, async_stream};
+use async_runtime::stream::AsyncStream;
+use std::time::Duration;
+
+#[async_stream]
+fn numbers(count: u32) -> u32 {
+    for i in 0..count {
+        await!(async_runtime::yield_now());
+        r#yield!(i);
+    }
+}
+
+#[async('static)]
+fn sum(count: u32) -> u32 {
+    let mut total = 0;
+    for number in await_each!(numbers(count)) {
+        total += number;
+    }
+    total
+}
+
+#[test]
+// The loop body runs for every value, the function continues after the stream ended
+fn test_await_each() {
+    assert_eq!(async_runtime::block_on(|callback| sum(4, callback)), 6);
+}
+
+#[async('static)]
+fn first_above(stream: AsyncStream<u32>, limit: u32) -> Option<u32> {
+    let mut found = None;
+    for number in await_each!(stream) {
+        if number > limit {
+            found = Some(number);
+            break;
+        }
+    }
+    found
+}
+
+#[test]
+// `break` stops consuming the stream, the rest of it is left for others
+fn test_break() {
+    let stream = AsyncStream::iter(vec![1, 5, 10, 20]);
+    let found = async_runtime::block_on(|callback| first_above(stream.clone(), 4, callback));
+    assert_eq!(found, Some(5));
+    assert_eq!(async_runtime::block_on(|callback| stream.next(callback)), Some(10));
+
+    let found = async_runtime::block_on(|callback| first_above(AsyncStream::iter(vec![1, 2]), 4, callback));
+    assert_eq!(found, None);
+}
+
+#[async('static)]
+fn delayed_pairs(count: u32) -> Vec<(u32, char)> {
+    let mut pairs = Vec::new();
+    'outer: for number in await_each!(numbers(count)) {
+        await!(async_runtime::sleep(Duration::from_millis(1)));
+        if number % 2 == 1 {
+            continue;
+        }
+        for letter in await_each!(AsyncStream::iter(vec!['a', 'b', 'c'])) {
+            if letter == 'c' {
+                continue 'outer;
+            }
+            if number == 4 {
+                break 'outer;
+            }
+            pairs.push((number, letter));
+        }
+    }
+    pairs
+}
+
+#[test]
+// Bodies can await, loops over streams nest and are left with labels
+fn test_nested() {
+    assert_eq!(async_runtime::block_on(|callback| delayed_pairs(10, callback)),
+               vec![(0, 'a'), (0, 'b'), (2, 'a'), (2, 'b')]);
+}
+
+#[async('static)]
+fn first_word(stream: AsyncStream<&'static str>) -> Result<&'static str, String> {
+    for word in await_each!(stream) {
+        if word.is_empty() {
+            return Err("empty word".to_string());
+        }
+        return Ok(word);
+    }
+    Err("no words".to_string())
+}
+
+#[test]
+// Returning from the body ends the function
+fn test_return() {
+    let word = async_runtime::block_on(|callback| first_word(AsyncStream::iter(vec!["a", "b"]), callback));
+    assert_eq!(word, Ok("a"));
+    let word = async_runtime::block_on(|callback| first_word(AsyncStream::iter(vec![""]), callback));
+    assert_eq!(word, Err("empty word".to_string()));
+    let word = async_runtime::block_on(|callback| first_word(AsyncStream::empty(), callback));
+    assert_eq!(word, Err("no words".to_string()));
+}
+
+#[async_stream]
+fn doubled(stream: AsyncStream<u32>) -> u32 {
+    for number in await_each!(stream) {
+        r#yield!(number * 2);
+    }
+}
+
+#[test]
+// Streams can be built out of other streams
+fn test_in_stream() {
+    let stream = doubled(numbers(3));
+    let found = async_runtime::block_on(|callback| first_above(stream.clone(), 0, callback));
+    assert_eq!(found, Some(2));
+    assert_eq!(async_runtime::block_on(|callback| stream.next(callback)), Some(4));
+    assert_eq!(async_runtime::block_on(|callback| stream.next(callback)), None);
+}
@@ -0,0 +1,164 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::{CancellationToken, Cancelled};
+use std::cell::{Cell, RefCell};
+use std::num::ParseIntError;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[async('static, cancellable)]
+fn count_ticks(token: CancellationToken, ticks: Rc<Cell<u32>>) -> u32 {
+    loop {
+        await!(async_runtime::sleep(millis(5)));
+        ticks.set(ticks.get() + 1);
+        if ticks.get() == 100 {
+            return ticks.get();
+        }
+    }
+}
+
+#[async]
+fn cancel_later(token: CancellationToken) {
+    await!(async_runtime::sleep(millis(22)));
+    token.cancel();
+}
+
+#[test]
+// The loop stops at the first await that completes after the token was cancelled
+fn test_cancel_loop() {
+    let ticks = Rc::new(Cell::new(0));
+    let counted = ticks.clone();
+    let result = async_runtime::block_on(move |callback| {
+        let token = CancellationToken::new();
+        count_ticks(token.clone(), counted, callback);
+        cancel_later(token);
+    });
+    assert_eq!(result, Err(Cancelled));
+    assert!(ticks.get() > 0 && ticks.get() < 5, "{} ticks", ticks.get());
+}
+
+#[async('static, cancellable)]
+fn parse_later(token: CancellationToken, text: &'static str) -> Result<u32, ParseIntError> {
+    await!(async_runtime::yield_now());
+    let value: u32 = text.parse()?;
+    Ok(value * 2)
+}
+
+#[test]
+// The value of a function that isn't cancelled is wrapped in `Ok`, errors returned with `?` as well
+fn test_not_cancelled() {
+    let doubled = async_runtime::block_on(|callback| parse_later(CancellationToken::new(), "21", callback));
+    assert_eq!(doubled, Ok(Ok(42)));
+
+    let failed = async_runtime::block_on(|callback| parse_later(CancellationToken::new(), "x", callback));
+    assert!(matches!(failed, Ok(Err(_))));
+}
+
+#[async('static, cancellable)]
+fn log_steps(token: CancellationToken, log: Rc<RefCell<Vec<u32>>>) {
+    log.borrow_mut().push(1);
+    await!(async_runtime::yield_now());
+    log.borrow_mut().push(2);
+    if log.borrow().len() == 2 {
+        token.cancel();
+    }
+    await!(async_runtime::yield_now());
+    log.borrow_mut().push(3);
+}
+
+#[test]
+fn test_cancel_unit_function() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let steps = log.clone();
+    let result = async_runtime::block_on(|callback| log_steps(CancellationToken::new(), steps, callback));
+    assert_eq!(result, Err(Cancelled));
+    assert_eq!(*log.borrow(), vec![1, 2]);
+
+    // A function called with a cancelled token doesn't start
+    let token = CancellationToken::new();
+    token.cancel();
+    let steps = log.clone();
+    assert_eq!(async_runtime::block_on(|callback| log_steps(token, steps, callback)), Err(Cancelled));
+    assert_eq!(log.borrow().len(), 2);
+}
+
+#[async('static, cancellable)]
+fn tick_borrowed(token: &CancellationToken, ticks: Rc<Cell<u32>>) {
+    loop {
+        await!(async_runtime::yield_now());
+        ticks.set(ticks.get() + 1);
+    }
+}
+
+#[test]
+// A borrowed token is cloned when the function is called, cancelling the original still stops it
+fn test_borrowed_token() {
+    let ticks = Rc::new(Cell::new(0));
+    let counted = ticks.clone();
+    let result = async_runtime::block_on(move |callback| {
+        let token = CancellationToken::new();
+        tick_borrowed(&token, counted, callback);
+        async_runtime::schedule(move || token.cancel());
+    });
+    assert_eq!(result, Err(Cancelled));
+    assert!(ticks.get() <= 2, "{} ticks", ticks.get());
+}
+
+#[test]
+fn test_child_token() {
+    let parent = CancellationToken::new();
+    let child = parent.child_token();
+    let other_child = parent.child_token();
+
+    child.cancel();
+    assert!(!parent.is_cancelled() && !other_child.is_cancelled());
+
+    parent.cancel();
+    assert!(other_child.is_cancelled());
+    assert!(parent.child_token().is_cancelled());
+}
+
+#[async('static)]
+fn sleep_unless_cancelled(token: CancellationToken) -> &'static str {
+    select! {
+        _ = token.cancelled() => "cancelled",
+        _ = async_runtime::sleep(millis(100)) => "slept",
+    }
+}
+
+#[test]
+fn test_await_cancelled() {
+    let token = CancellationToken::new();
+    let waiting = token.clone();
+    let result = async_runtime::block_on(move |callback| {
+        sleep_unless_cancelled(waiting, callback);
+        async_runtime::schedule(move || token.cancel());
+    });
+    assert_eq!(result, "cancelled");
+}
+
+#[async(cancellable, future)]
+fn add_one_later(token: CancellationToken, i: u32) -> u32 {
+    await!(async_runtime::yield_now());
+    i + 1
+}
+
+#[async('static)]
+fn add_with_futures() -> (Result<u32, Cancelled>, Result<u32, Cancelled>) {
+    let token = CancellationToken::new();
+    let added = await!((add_one_later(token.clone(), 1)));
+    token.cancel();
+    let cancelled = await!((add_one_later(token, 1)));
+    (added, cancelled)
+}
+
+#[test]
+fn test_cancellable_future() {
+    assert_eq!(async_runtime::block_on(add_with_futures), (Ok(2), Err(Cancelled)));
+}
@@ -0,0 +1,164 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::channel::{broadcast, mpsc, oneshot, watch, RecvError, SendError};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[async]
+fn double_later(value: u32, sender: oneshot::Sender<u32>) {
+    await!(async_runtime::yield_now());
+    sender.send(value * 2).unwrap();
+}
+
+#[async('static)]
+fn oneshot_round_trip() -> (Result<u32, RecvError>, Result<u32, RecvError>) {
+    let (sender, receiver) = oneshot::channel();
+    double_later(21, sender);
+    let doubled = await!(receiver.recv());
+
+    // Dropping the sender closes the channel
+    let (sender, receiver) = oneshot::channel::<u32>();
+    drop(sender);
+    let closed = await!(receiver.recv());
+    (doubled, closed)
+}
+
+#[test]
+fn test_oneshot() {
+    assert_eq!(async_runtime::block_on(oneshot_round_trip), (Ok(42), Err(RecvError)));
+
+    let (sender, receiver) = oneshot::channel();
+    drop(receiver);
+    assert_eq!(sender.send(1), Err(1));
+}
+
+#[async]
+fn produce(sender: mpsc::Sender<u32>, values: Vec<u32>, log: Rc<RefCell<Vec<String>>>) {
+    for value in values {
+        let sent = await!(sender.clone().send(value));
+        assert!(sent.is_ok());
+        log.borrow_mut().push(format!("sent {}", value));
+    }
+}
+
+#[async('static)]
+fn consume(receiver: mpsc::Receiver<u32>, log: Rc<RefCell<Vec<String>>>) -> u32 {
+    let mut sum = 0;
+    while let Some(value) = await!(receiver.clone().recv()) {
+        log.borrow_mut().push(format!("received {}", value));
+        sum += value;
+    }
+    sum
+}
+
+#[async('static)]
+fn sum_over_mpsc(log: Rc<RefCell<Vec<String>>>) -> u32 {
+    let (sender, receiver) = mpsc::channel(1);
+    produce(sender.clone(), vec![1, 2], log.clone());
+    // The receiver sees the end once both senders are gone
+    produce(sender, vec![10, 20], log.clone());
+    await!(consume(receiver, log))
+}
+
+#[test]
+fn test_mpsc() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(async_runtime::block_on(|callback| sum_over_mpsc(log.clone(), callback)), 33);
+
+    // With room for one value, a sender only gets ahead by one
+    let log = log.borrow();
+    for (i, entry) in log.iter().enumerate() {
+        if entry.starts_with("sent") {
+            let received = log[..i].iter().filter(|entry| entry.starts_with("received")).count();
+            let sent = log[..i].iter().filter(|entry| entry.starts_with("sent")).count();
+            assert!(sent <= received + 1, "{:?}", *log);
+        }
+    }
+}
+
+#[async('static)]
+fn send_to_nobody() -> Result<(), SendError<u32>> {
+    let (sender, receiver) = mpsc::channel(1);
+    sender.try_send(1).unwrap();
+    assert_eq!(sender.try_send(2), Err(mpsc::TrySendError::Full(2)));
+    // Waits for capacity until the receiver is dropped
+    async_runtime::schedule(move || drop(receiver));
+    await!(sender.send(3))
+}
+
+#[test]
+fn test_mpsc_closed() {
+    assert_eq!(async_runtime::block_on(send_to_nobody), Err(SendError(3)));
+}
+
+#[async('static)]
+fn collect(receiver: broadcast::Receiver<u32>) -> Vec<Result<u32, broadcast::RecvError>> {
+    let mut received = Vec::new();
+    loop {
+        let value = await!(receiver.clone().recv());
+        received.push(value);
+        if value == Err(broadcast::RecvError::Closed) {
+            return received;
+        }
+    }
+}
+
+#[async]
+fn send_in_bursts(sender: broadcast::Sender<u32>) {
+    assert_eq!(sender.send(1), Ok(2));
+    await!(async_runtime::yield_now());
+    // Three values at once don't fit, receivers miss the first of them
+    sender.send(2).unwrap();
+    sender.send(3).unwrap();
+    sender.send(4).unwrap();
+}
+
+#[async('static)]
+fn broadcast_values() -> (Vec<Result<u32, broadcast::RecvError>>, Vec<Result<u32, broadcast::RecvError>>) {
+    let (sender, first) = broadcast::channel(2);
+    let second = sender.subscribe();
+    send_in_bursts(sender);
+    join!(collect(first), collect(second))
+}
+
+#[test]
+fn test_broadcast() {
+    use async_runtime::channel::broadcast::RecvError::{Closed, Lagged};
+    let (first, second) = async_runtime::block_on(broadcast_values);
+    assert_eq!(first, vec![Ok(1), Err(Lagged(1)), Ok(3), Ok(4), Err(Closed)]);
+    assert_eq!(second, first);
+}
+
+#[async('static)]
+fn watch_changes(receiver: watch::Receiver<u32>) -> Vec<u32> {
+    let mut seen = vec![receiver.get()];
+    while let Ok(()) = await!(receiver.clone().changed()) {
+        seen.push(receiver.get_and_update());
+    }
+    seen
+}
+
+#[async]
+fn update_twice(sender: watch::Sender<u32>) {
+    sender.send(1).unwrap();
+    await!(async_runtime::yield_now());
+    // Receivers only see the latest of values sent in between
+    sender.send(2).unwrap();
+    sender.send(3).unwrap();
+}
+
+#[test]
+fn test_watch() {
+    let (sender, receiver) = watch::channel(0);
+    let seen = async_runtime::block_on(move |callback| {
+        watch_changes(receiver, callback);
+        update_twice(sender);
+    });
+    assert_eq!(seen, vec![0, 1, 3]);
+
+    let (sender, receiver) = watch::channel(0);
+    drop(receiver);
+    assert_eq!(sender.send(1), Err(SendError(1)));
+}
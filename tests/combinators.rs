@@ -0,0 +1,127 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::{async, async_stream};
+use async_runtime::stream::{AsyncStream, Then};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[async('static)]
+fn collect<T: 'static>(stream: AsyncStream<T>) -> Vec<T> {
+    await!(stream.fold(Vec::new(), |mut values, value| {
+        values.push(value);
+        values
+    }))
+}
+
+#[async_stream]
+fn numbers(count: u32) -> u32 {
+    for i in 1..=count {
+        await!(async_runtime::yield_now());
+        r#yield!(i);
+    }
+}
+
+#[async('static)]
+fn double_later(value: u32, millis: u64) -> u32 {
+    await!(async_runtime::sleep(Duration::from_millis(millis)));
+    value * 2
+}
+
+#[test]
+fn test_map_filter() {
+    let stream = numbers(6).map(|i| i * 10).filter(|i| i % 20 == 0);
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)), vec![20, 40, 60]);
+}
+
+#[test]
+// Async functions are awaited for each value, one after another
+fn test_then() {
+    let stream = numbers(3).then(|i, callback: Then<u32>| double_later(i, 1, callback));
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)), vec![2, 4, 6]);
+}
+
+#[test]
+// Values after the ones taken are never produced
+fn test_take_skip() {
+    let produced = Rc::new(RefCell::new(0));
+    let counter = produced.clone();
+    let stream = AsyncStream::iter(1..).map(move |i| {
+        *counter.borrow_mut() += 1;
+        i
+    });
+    let stream = stream.skip(2).take(3);
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)), vec![3, 4, 5]);
+    assert_eq!(*produced.borrow(), 5);
+
+    let stream = numbers(2).skip(5);
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)), Vec::<u32>::new());
+}
+
+#[test]
+fn test_chunks() {
+    let stream = numbers(7).chunks(3);
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)),
+               vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+}
+
+#[test]
+// Results come in the order they complete, no more than the limit run at once
+fn test_buffer_unordered() {
+    let running = Rc::new(RefCell::new((0, 0)));
+    let counter = running.clone();
+    let delays = AsyncStream::iter(vec![40, 10, 20, 1]);
+    let stream = delays.buffer_unordered(2, move |millis: u64, callback: Then<u64>| {
+        counter.borrow_mut().0 += 1;
+        let (now, max) = *counter.borrow();
+        counter.borrow_mut().1 = max.max(now);
+
+        let counter = counter.clone();
+        async_runtime::sleep(Duration::from_millis(millis), move |_| {
+            counter.borrow_mut().0 -= 1;
+            callback(millis);
+        });
+    });
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)), vec![10, 20, 1, 40]);
+    assert_eq!(running.borrow().1, 2);
+}
+
+#[async_stream]
+fn ticks(name: &'static str, count: u32, millis: u64) -> String {
+    for i in 0..count {
+        await!(async_runtime::sleep(Duration::from_millis(millis)));
+        r#yield!(format!("{}{}", name, i));
+    }
+}
+
+#[test]
+// Values of both streams arrive as they are produced
+fn test_merge() {
+    let stream = ticks("a", 2, 40).merge(ticks("b", 4, 4));
+    let values = async_runtime::block_on(|callback| collect(stream, callback));
+    assert_eq!(values.len(), 6);
+    assert_eq!(values[0], "b0");
+    assert_eq!(values[5], "a1");
+}
+
+#[test]
+// Pairs are made until the shorter stream ended
+fn test_zip() {
+    let stream = numbers(5).zip(ticks("t", 3, 1));
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)),
+               vec![(1, "t0".to_string()), (2, "t1".to_string()), (3, "t2".to_string())]);
+}
+
+#[async('static)]
+fn total_of_doubles(count: u32) -> u32 {
+    let doubles = numbers(count).then(|i, callback: Then<u32>| double_later(i, 0, callback));
+    let total = await!(doubles.fold(0, |total, i| total + i));
+    total
+}
+
+#[test]
+// Folding a stream can be awaited
+fn test_fold() {
+    assert_eq!(async_runtime::block_on(|callback| total_of_doubles(4, callback)), 20);
+}
@@ -0,0 +1,82 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Empty directory only used by one test
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("async-runtime-fs-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[async('static)]
+fn write_copy_and_list(dir: PathBuf) -> io::Result<(Vec<u8>, u64, Vec<String>)> {
+    let original = dir.join("original.txt");
+    let copy = dir.join("copy.txt");
+
+    await!(fs::write(original.clone(), b"hello file".to_vec()))?;
+    let copied = await!(fs::copy(original, copy.clone()))?;
+    let contents = await!(fs::read(copy.clone()))?;
+    let metadata = await!(fs::metadata(copy))?;
+    let len = metadata.len();
+    assert_eq!(copied, len);
+
+    let entries = await!(fs::read_dir(dir))?;
+    let mut names: Vec<String> = entries.into_iter()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    Ok((contents, len, names))
+}
+
+#[test]
+fn test_fs_helpers() {
+    let dir = test_dir("helpers");
+    let (contents, len, names) = async_runtime::block_on(|callback| write_copy_and_list(dir.clone(), callback))
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(contents, b"hello file");
+    assert_eq!(len, 10);
+    assert_eq!(names, vec!["copy.txt", "original.txt"]);
+}
+
+#[async('static)]
+fn write_then_read_in_chunks(path: PathBuf) -> io::Result<Vec<Vec<u8>>> {
+    let file = await!(fs::File::create(path.clone()))?;
+    await!(file.clone().write_all(b"abcdefg".to_vec()))?;
+    await!(file.sync_all())?;
+
+    let file = await!(fs::open(path))?;
+    let mut chunks = Vec::new();
+    loop {
+        let chunk = await!(file.clone().read(3))?;
+        if chunk.is_empty() {
+            return Ok(chunks);
+        }
+        chunks.push(chunk);
+    }
+}
+
+#[test]
+fn test_file() {
+    let dir = test_dir("file");
+    let chunks = async_runtime::block_on(|callback| write_then_read_in_chunks(dir.join("chunks"), callback));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(chunks.unwrap(), vec![b"abc".to_vec(), b"def".to_vec(), b"g".to_vec()]);
+}
+
+#[test]
+fn test_missing_file() {
+    let dir = test_dir("missing");
+    let result = async_runtime::block_on(|callback| fs::read(dir.join("missing"), callback));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(result.err().map(|err| err.kind()), Some(io::ErrorKind::NotFound));
+}
@@ -0,0 +1,206 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::Duration;
+
+// Counts how often the future was woken
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn poll<F: Future + Unpin>(future: &mut F, waker: &Waker) -> Poll<F::Output> {
+    Pin::new(future).poll(&mut Context::from_waker(waker))
+}
+
+thread_local!(static DEFERRED: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new()));
+
+// Completes once `run_deferred` is called
+fn deferred_double(i: i32, callback: impl FnOnce(i32) + 'static) {
+    DEFERRED.with(|deferred| deferred.borrow_mut().push(Box::new(move || callback(i * 2))));
+}
+
+fn run_deferred() {
+    while let Some(callback) = DEFERRED.with(|deferred| deferred.borrow_mut().pop()) {
+        callback();
+    }
+}
+
+#[async]
+fn add_one(i: i32) -> i32 {
+    i + 1
+}
+
+#[async(future)]
+fn ready_sum(a: i32, b: i32) -> i32 {
+    await!(add_one(a)) + b
+}
+
+#[async(future)]
+fn doubled_twice(i: i32) -> i32 {
+    let once = await!(deferred_double(i));
+    await!(deferred_double(once))
+}
+
+#[async(future)]
+fn record(log: std::rc::Rc<RefCell<Vec<i32>>>) {
+    for i in 0..3 {
+        log.borrow_mut().push(await!(add_one(i)));
+    }
+}
+
+#[test]
+// Bodies that complete synchronously are ready on the first poll
+fn test_ready_future() {
+    let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+    let mut future = ready_sum(1, 2);
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(4));
+}
+
+#[test]
+// The future is woken once the final callback is called
+fn test_pending_future() {
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+
+    let mut future = doubled_twice(3);
+    assert_eq!(poll(&mut future, &waker), Poll::Pending);
+    assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+    run_deferred();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(12));
+}
+
+#[test]
+// Nothing runs before the future is polled
+fn test_lazy_start() {
+    let log = std::rc::Rc::new(RefCell::new(Vec::new()));
+    let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+
+    let mut future = record(log.clone());
+    assert!(log.borrow().is_empty());
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(()));
+    assert_eq!(*log.borrow(), vec![1, 2, 3]);
+}
+
+struct Account {
+    balance: i32,
+}
+
+impl Account {
+    #[async(future)]
+    fn balance_after(self, deposit: i32) -> i32 {
+        self.balance + await!(add_one(deposit)) - 1
+    }
+}
+
+#[test]
+fn test_method_future() {
+    let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+    let account = Account { balance: 10 };
+    let mut future = account.balance_after(5);
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(15));
+}
+
+#[test]
+fn test_from_callback() {
+    let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+    let mut future = async_runtime::future::from_callback(|callback| add_one(4, callback));
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(5));
+
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(counter.clone());
+    let mut future = async_runtime::future::from_callback(|callback| deferred_double(4, callback));
+    assert_eq!(poll(&mut future, &waker), Poll::Pending);
+    run_deferred();
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    assert_eq!(poll(&mut future, &waker), Poll::Ready(8));
+}
+
+// Pending on the first poll, wakes itself right away
+struct YieldOnce(Option<i32>, bool);
+
+impl Future for YieldOnce {
+    type Output = i32;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<i32> {
+        if self.1 {
+            return Poll::Ready(self.0.take().unwrap());
+        }
+        self.1 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+// Completed by another thread
+struct Delayed {
+    shared: Arc<Mutex<(Option<i32>, Option<Waker>)>>,
+}
+
+fn delayed(value: i32) -> Delayed {
+    let shared = Arc::new(Mutex::new((None, None::<Waker>)));
+    let remote = shared.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        let mut remote = remote.lock().unwrap();
+        remote.0 = Some(value);
+        if let Some(waker) = remote.1.take() {
+            waker.wake();
+        }
+    });
+    Delayed { shared }
+}
+
+impl Future for Delayed {
+    type Output = i32;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<i32> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.0.take() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                shared.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[async('static)]
+fn sum_of_futures(i: i32) -> i32 {
+    let ready = await!((std::future::ready(i)));
+    let yielded = YieldOnce(Some(ready + 1), false);
+    let yielded = await!(yielded);
+    let remote = await!((delayed(yielded + 1)));
+    let converted = await!((ready_sum(remote, 1)));
+    ready + yielded + remote + converted
+}
+
+#[test]
+// Anything that isn't a call is awaited as a future on the event loop
+fn test_await_future() {
+    assert_eq!(async_runtime::block_on(|callback| sum_of_futures(1, callback)), 1 + 2 + 3 + 5);
+}
+
+#[async('static)]
+fn join_remote() -> (i32, i32) {
+    join!((delayed(1)), (delayed(2)))
+}
+
+#[test]
+fn test_join_futures() {
+    assert_eq!(async_runtime::block_on(join_remote), (1, 2));
+}
@@ -1,8 +1,13 @@
-#![feature(plugin, custom_attribute, box_syntax)]
-#![plugin(async)]
+// Sync callers may still pass a reference to a closure as the callback
+#![allow(clippy::needless_borrows_for_generic_args)]
+
+extern crate async;
+
+use async::async;
+use std::cell::RefCell;
+use std::rc::Rc;
 // Run the following command to view expanded code:
-// cargo rustc --bin async_tests -- --pretty=expanded
-// -Z unstable-options -o target/debug/main.expanded.rs
+// cargo expand --test lib
 
 #[async]
 fn simple_return() -> i32 {
@@ -67,3 +72,766 @@ fn test_tup() {
 	let (a, b) = (await!(simple_return()), await!(simple_return()));
 	assert_eq!(a, b);
 }
+
+#[test]
+#[async]
+fn test_join() {
+    let (a, b, c) = join!(simple_return(), add_one(1), add_one(await!(add_one(2))));
+    assert_eq!((a, b, c), (1, 2, 4));
+
+    let mut sum = 0;
+    for i in 0..3 {
+        let (single,) = join!(add_one(i));
+        sum += single;
+    }
+    assert_eq!(sum, 6);
+}
+
+// A macro of the user's that happens to share its name with the special forms
+#[macro_export]
+macro_rules! join {
+    ($($part:expr),*) => { vec![$($part.to_string()),*].join("-") }
+}
+
+#[test]
+#[async]
+// Only invocations the attribute converts are treated as the special forms,
+// ones in closures, nested items or named by a path are left to the macros in scope
+fn test_own_join_macro() {
+    let joined = |a: i32| join!(a, "b");
+    fn nested() -> String {
+        join!(1, 2)
+    }
+    let (a, b) = join!(add_one(0), add_one(1));
+    assert_eq!(joined(await!(add_one(a))), "2-b");
+    assert_eq!(nested(), "1-2");
+    assert_eq!(crate::join!(b, "c"), "2-c");
+}
+
+#[test]
+#[async]
+fn test_while_loop() {
+    let mut i = 0;
+    let mut sum = 0;
+    while i < 5 {
+        sum += await!(add_one(i));
+        i += 1;
+    }
+    assert_eq!(sum, 15);
+}
+
+#[test]
+#[async]
+fn test_for_loop() {
+    let mut results = Vec::new();
+    for i in 0..3 {
+        results.push(await!(add_one(i)));
+    }
+    assert_eq!(results, vec![1, 2, 3]);
+}
+
+#[test]
+#[async]
+fn test_while_let_loop() {
+    let mut items = vec![1, 2, 3];
+    let mut total = 0;
+    while let Some(item) = items.pop() {
+        total += await!(add_one(item));
+    }
+    assert_eq!(total, 9);
+}
+
+#[test]
+#[async]
+fn test_loop_break_value() {
+    let mut i = 0;
+    let doubled = loop {
+        i = await!(add_one(i));
+        if i == 4 {
+            break i * 2;
+        }
+    };
+    assert_eq!(doubled, 8);
+}
+
+#[test]
+#[async]
+// Variables only named inside format strings are carried into the loop as well
+fn test_loop_format_args() {
+    let prefix = "n";
+    let width = 3;
+    let mut i = 0;
+    let mut line = String::new();
+    while i < 2 {
+        i = await!(add_one(i));
+        line = format!("{prefix}-{i:>width$}|{{prefix}}");
+    }
+    assert_eq!(line, "n-  2|{prefix}");
+}
+
+#[test]
+#[async]
+fn test_loop_continue() {
+    let mut odd = Vec::new();
+    for i in 0..6 {
+        if i % 2 == 0 {
+            continue;
+        }
+        odd.push(await!(add_one(i)) - 1);
+    }
+    assert_eq!(odd, vec![1, 3, 5]);
+}
+
+#[test]
+#[async]
+fn test_labeled_loops() {
+    let mut pairs = Vec::new();
+    'outer: for i in 0..4 {
+        for j in 0..4 {
+            if j > i {
+                continue 'outer;
+            }
+            if i == 2 {
+                break 'outer;
+            }
+            pairs.push((i, await!(add_one(j)) - 1));
+        }
+    }
+    assert_eq!(pairs, vec![(0, 0), (1, 0), (1, 1)]);
+}
+
+#[async]
+fn first_above(limit: i32) -> i32 {
+    let mut i = 0;
+    loop {
+        i = await!(add_one(i));
+        if i > limit {
+            return i;
+        }
+    }
+}
+
+#[test]
+#[async]
+fn test_return_from_loop() {
+    assert_eq!(await!(first_above(3)), 4);
+}
+
+thread_local!(static DEFERRED: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new()));
+
+// Completes once `run_deferred` is called instead of calling the callback right away
+fn deferred_add_one(i: i32, callback: impl FnOnce(i32) + 'static) {
+    DEFERRED.with(|deferred| deferred.borrow_mut().push(Box::new(move || callback(i + 1))));
+}
+
+fn run_deferred() {
+    while let Some(callback) = DEFERRED.with(|deferred| deferred.borrow_mut().pop()) {
+        callback();
+    }
+}
+
+#[async]
+fn deferred_loop(results: Rc<RefCell<Vec<i32>>>) {
+    for i in 0..3 {
+        let n = await!(deferred_add_one(i));
+        results.borrow_mut().push(n);
+    }
+    results.borrow_mut().push(0);
+}
+
+#[test]
+// Each iteration only continues once the awaited callback fires
+fn test_loop_resumes_from_callback() {
+    let results = Rc::new(RefCell::new(Vec::new()));
+    deferred_loop(results.clone());
+    assert_eq!(*results.borrow(), vec![]);
+
+    run_deferred();
+    assert_eq!(*results.borrow(), vec![1, 2, 3, 0]);
+}
+
+#[async]
+fn deferred_join(results: Rc<RefCell<Vec<(i32, i32)>>>) {
+    let (a, b) = join!(deferred_add_one(1), deferred_add_one(10));
+    results.borrow_mut().push((a, b));
+}
+
+#[test]
+// Both calls are started before either completes, the results are in source order
+fn test_join_is_concurrent() {
+    let results = Rc::new(RefCell::new(Vec::new()));
+    deferred_join(results.clone());
+    assert_eq!(DEFERRED.with(|deferred| deferred.borrow().len()), 2);
+
+    // Completes the second call first
+    run_deferred();
+    assert_eq!(*results.borrow(), vec![(2, 11)]);
+}
+
+#[async]
+fn deferred_select(results: Rc<RefCell<Vec<i32>>>) {
+    let first = select! {
+        n = deferred_add_one(1) => n * 10,
+        n = add_one(5) => n,
+    };
+    results.borrow_mut().push(first);
+
+    let second = select! {
+        n = add_one(1) => {
+            let n = await!(deferred_add_one(n));
+            n * 100
+        }
+        _ = deferred_add_one(2) => 0,
+    };
+    results.borrow_mut().push(second);
+}
+
+#[async('static)]
+fn select_with_commas() -> (i32, i32) {
+    // Commas inside of a body that isn't a block are nested in its parentheses
+    select! {
+        n = add_one(1) => (n, std::cmp::max(n, 10)),
+        _ = deferred_add_one(2) => (0, 0)
+    }
+}
+
+#[test]
+// Continues with the first call to complete, the others are ignored
+fn test_select() {
+    let results = Rc::new(RefCell::new(Vec::new()));
+    deferred_select(results.clone());
+    assert_eq!(*results.borrow(), vec![6]);
+
+    // The second select only started its first call, which completed right away
+    assert_eq!(DEFERRED.with(|deferred| deferred.borrow().len()), 2);
+    run_deferred();
+    assert_eq!(*results.borrow(), vec![6, 300]);
+
+    let pair = Rc::new(RefCell::new(None));
+    let slot = pair.clone();
+    select_with_commas(move |value| *slot.borrow_mut() = Some(value));
+    assert_eq!(*pair.borrow(), Some((2, 10)));
+}
+
+#[test]
+#[async]
+fn test_if_else_value() {
+    let mut a = 1;
+    let b = if a > 0 {
+        a = await!(add_one(a));
+        a * 10
+    } else {
+        0
+    };
+    assert_eq!((a, b), (2, 20));
+}
+
+#[test]
+#[async]
+fn test_if_without_else() {
+    let mut visited = Vec::new();
+    for i in 0..4 {
+        if i % 2 == 1 {
+            visited.push(await!(add_one(i)));
+        }
+        visited.push(i);
+    }
+    assert_eq!(visited, vec![0, 2, 1, 2, 4, 3]);
+}
+
+#[test]
+#[async]
+fn test_else_if_chain() {
+    let mut labels = Vec::new();
+    for i in 0..3 {
+        let label = if i == 0 {
+            "zero"
+        } else if await!(add_one(i)) == 2 {
+            "one"
+        } else {
+            "many"
+        };
+        labels.push(label);
+    }
+    assert_eq!(labels, vec!["zero", "one", "many"]);
+}
+
+#[test]
+#[async]
+fn test_match_value() {
+    let mut total = 0;
+    for option in vec![Some(1), None, Some(3)] {
+        total += match option {
+            Some(n) => await!(add_one(n)),
+            None => 10,
+        };
+    }
+    assert_eq!(total, 16);
+}
+
+#[async]
+fn sign(n: i32) -> &'static str {
+    match n {
+        0 => return "zero",
+        n if n < 0 => {
+            await!(add_one(n));
+            "negative"
+        }
+        _ => "positive",
+    }
+}
+
+#[test]
+#[async]
+fn test_return_from_branch() {
+    assert_eq!(await!(sign(0)), "zero");
+    assert_eq!(await!(sign(-2)), "negative");
+    assert_eq!(await!(sign(5)), "positive");
+}
+
+#[test]
+#[async]
+fn test_block_value() {
+    let a = {
+        let b = await!(add_one(1));
+        b * 2
+    };
+    assert_eq!(a, 4);
+}
+
+unsafe fn unchecked_add(a: i32, b: i32) -> i32 {
+    a.unchecked_add(b)
+}
+
+#[test]
+#[async]
+// Unsafe blocks are converted like plain blocks and stay unsafe
+fn test_unsafe_block() {
+    let x = unsafe { await!(add_one(1)) };
+    assert_eq!(x, 2);
+    let y = unsafe {
+        let a = await!(add_one(x));
+        unchecked_add(a, await!(add_one(a)))
+    };
+    assert_eq!(y, 7);
+    let z = unsafe { unchecked_add(y, 1) } + await!(add_one(0));
+    assert_eq!(z, 9);
+}
+
+macro_rules! async_sum {
+    ($name:ident, $a:expr, $b:expr) => {
+        #[async]
+        fn $name() -> i32 {
+            $a + $b
+        }
+    };
+}
+
+// Expressions passed as fragments are wrapped in invisible groups
+async_sum!(fragment_sum, await!(add_one(1)), await!(add_one(2)));
+
+#[test]
+#[async]
+fn test_macro_fragments() {
+    assert_eq!(await!(fragment_sum()), 5);
+}
+
+#[async]
+fn sync_branches(n: i32) -> i32 {
+    let a = {
+        let b = n + 1;
+        b * 2
+    };
+    let c = if a > 4 { a } else { 0 };
+    let d = match c {
+        0 => 1,
+        c => c * 2,
+    };
+    await!(add_one(d))
+}
+
+#[test]
+#[async]
+// The tail expression of a nested block is its value, not the return value of the function
+fn test_sync_branch_values() {
+    assert_eq!(await!(sync_branches(1)), 2);
+    assert_eq!(await!(sync_branches(2)), 13);
+}
+
+#[async]
+fn deferred_branch(n: i32, results: Rc<RefCell<Vec<i32>>>) {
+    if n > 0 {
+        let m = await!(deferred_add_one(n));
+        results.borrow_mut().push(m);
+    } else {
+        results.borrow_mut().push(n);
+    }
+    results.borrow_mut().push(0);
+}
+
+#[test]
+// Code after the branch runs once, after the awaited callback fires
+fn test_branch_resumes_from_callback() {
+    let results = Rc::new(RefCell::new(Vec::new()));
+    deferred_branch(1, results.clone());
+    deferred_branch(0, results.clone());
+    assert_eq!(*results.borrow(), vec![0, 0]);
+
+    run_deferred();
+    assert_eq!(*results.borrow(), vec![0, 0, 2, 0]);
+}
+
+#[derive(Debug, PartialEq)]
+enum FetchError {
+    NotFound(i32),
+    Parse,
+}
+
+impl From<std::num::ParseIntError> for FetchError {
+    fn from(_: std::num::ParseIntError) -> Self {
+        FetchError::Parse
+    }
+}
+
+#[async]
+fn fetch(id: i32) -> Result<String, FetchError> {
+    let n = await!(add_one(id));
+    if n > 3 {
+        return Err(FetchError::NotFound(id));
+    }
+    Ok(format!("{}{}", n, n))
+}
+
+#[async]
+fn fetch_number(id: i32, steps: Rc<RefCell<Vec<i32>>>) -> Result<i32, FetchError> {
+    let text = await!(fetch(id))?;
+    steps.borrow_mut().push(id);
+    let n: i32 = text.parse()?;
+    let zero = await!(fetch(0))?;
+    Ok(n + zero.len() as i32)
+}
+
+#[test]
+#[async]
+fn test_try_ok() {
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(fetch_number(1, steps.clone())), Ok(24));
+    assert_eq!(*steps.borrow(), vec![1]);
+}
+
+#[test]
+#[async]
+// An error skips every continuation after the `?`
+fn test_try_err() {
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(fetch_number(3, steps.clone())), Err(FetchError::NotFound(3)));
+    assert_eq!(*steps.borrow(), vec![]);
+}
+
+#[async]
+fn sum_until_error(ids: Vec<i32>) -> Result<i32, FetchError> {
+    let mut sum = 0;
+    for id in ids {
+        sum += await!(fetch_number(id, Rc::new(RefCell::new(Vec::new()))))?;
+    }
+    Ok(sum)
+}
+
+#[test]
+#[async]
+fn test_try_in_loop() {
+    assert_eq!(await!(sum_until_error(vec![0, 1])), Ok(13 + 24));
+    assert_eq!(await!(sum_until_error(vec![0, 5, 1])), Err(FetchError::NotFound(5)));
+}
+
+#[test]
+#[async]
+// Errors converted by `From` reach the callback as well
+fn test_try_converts_error() {
+    assert_eq!(await!(parse_fetched(2)), Err(FetchError::Parse));
+}
+
+#[async]
+fn parse_fetched(id: i32) -> Result<i32, FetchError> {
+    let text = await!(fetch(id))? + "x";
+    Ok(text.parse::<i32>()?)
+}
+
+#[async]
+fn checked_add_one(i: i32) -> Option<i32> {
+    if i < 2 {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+#[async]
+fn add_two_checked(i: i32, steps: Rc<RefCell<Vec<i32>>>) -> Option<i32> {
+    let once = await!(checked_add_one(i))?;
+    steps.borrow_mut().push(once);
+    Some(await!(checked_add_one(once))?)
+}
+
+#[test]
+#[async]
+// `None` reaches the callback of functions returning an `Option`
+fn test_try_option() {
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(add_two_checked(0, steps.clone())), Some(2));
+    assert_eq!(await!(add_two_checked(1, steps.clone())), None);
+    assert_eq!(await!(add_two_checked(2, steps.clone())), None);
+    assert_eq!(*steps.borrow(), vec![1, 2]);
+}
+
+struct Counter {
+    count: i32,
+}
+
+impl Counter {
+    #[async]
+    fn add(&mut self, n: i32) -> i32 {
+        self.count += await!(add_one(n));
+        self.count
+    }
+
+    #[async]
+    fn get(&self) -> i32 {
+        await!(add_one(self.count)) - 1
+    }
+
+    #[async]
+    fn add_all(&mut self, items: Vec<i32>) {
+        for item in items {
+            self.count += await!(add_one(item));
+            if self.count > 10 {
+                self.count = 0;
+            }
+        }
+    }
+
+    #[async]
+    fn into_count(self) -> i32 {
+        await!(self.get())
+    }
+
+    #[async]
+    fn doubled(&self) -> i32 {
+        // Nested items have a `self` of their own
+        struct Pair(i32, i32);
+        impl Pair {
+            fn sum(&self) -> i32 {
+                self.0 + self.1
+            }
+        }
+        fn twice(n: i32) -> i32 {
+            Pair(n, n).sum()
+        }
+        let apply: fn(i32) -> i32 = twice;
+        apply(await!(add_one(self.count)) - 1)
+    }
+}
+
+trait Source {
+    #[async]
+    fn fetch(&self, id: i32) -> i32;
+
+    #[async]
+    fn fetch_twice(&self, id: i32) -> i32 {
+        let first = await!(self.fetch(id));
+        first + await!(self.fetch(id))
+    }
+}
+
+impl Source for Counter {
+    #[async]
+    fn fetch(&self, id: i32) -> i32 {
+        await!(add_one(id)) + self.count
+    }
+}
+
+#[test]
+// The final callback comes after the receiver and the other arguments
+fn test_methods() {
+    let mut counter = Counter { count: 1 };
+    let mut result = None;
+    counter.add(2, |n| result = Some(n));
+    assert_eq!(result, Some(4));
+
+    counter.add_all(vec![1, 2, 3]);
+    assert_eq!(counter.count, 0);
+
+    counter.count = 5;
+    counter.into_count(|n| result = Some(n));
+    assert_eq!(result, Some(5));
+}
+
+#[test]
+#[async]
+// Shared references can be used again in the continuation of an await
+fn test_shared_self() {
+    let counter = &Counter { count: 10 };
+    assert_eq!(await!(counter.get()), 10);
+    assert_eq!(await!(counter.fetch(1)), 12);
+    assert_eq!(await!(counter.fetch_twice(1)), 24);
+    assert_eq!(await!(counter.doubled()), 20);
+}
+
+#[async]
+fn new_counter(count: i32) -> Counter {
+    Counter { count }
+}
+
+#[async]
+fn adder(n: i32) -> fn(i32) -> i32 {
+    await!(add_one(n));
+    |i| i + 10
+}
+
+#[test]
+#[async]
+// Awaits in receivers and callees are evaluated before the arguments
+fn test_await_in_call_chain() {
+    assert_eq!(await!(new_counter(3)).count.pow(2), 9);
+    assert_eq!(await!(simple_return()).max(await!(add_one(5))), 6);
+    assert_eq!((await!(adder(1)))(await!(simple_return())), 11);
+    assert_eq!(await!(await!(new_counter(4)).into_count()), 4);
+    assert_eq!(await!(fetch(1)).unwrap().len(), await!(fetch(1)).map(|s| s.len()).unwrap());
+
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    let larger = await!(fetch_number(2, steps.clone())).unwrap().max(await!(fetch_number(1, steps.clone())).unwrap());
+    assert_eq!(larger, 35);
+    assert_eq!(*steps.borrow(), vec![2, 1]);
+}
+
+#[async]
+fn logged(value: i32, log: Rc<RefCell<Vec<i32>>>) -> bool {
+    await!(add_one(value));
+    log.borrow_mut().push(value);
+    value > 0
+}
+
+#[test]
+#[async]
+// The right side of `&&` and `||` only runs if the left side didn't decide the result
+fn test_short_circuit() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert!(!(false && await!(logged(1, log.clone()))));
+    assert!(true || await!(logged(2, log.clone())));
+    assert!(log.borrow().is_empty());
+
+    assert!(await!(logged(3, log.clone())) && await!(logged(4, log.clone())));
+    assert!(!(await!(logged(-5, log.clone())) && await!(logged(6, log.clone()))));
+    assert!(await!(logged(-7, log.clone())) || await!(logged(8, log.clone())) || await!(logged(9, log.clone())));
+    assert_eq!(*log.borrow(), vec![3, 4, -5, -7, 8]);
+}
+
+#[async]
+fn classify(n: i32, log: Rc<RefCell<Vec<i32>>>) -> &'static str {
+    if n == 0 {
+        "zero"
+    } else if n > 100 && await!(logged(n, log.clone())) {
+        "large"
+    } else if await!(logged(-n, log.clone())) {
+        "negative"
+    } else {
+        "small"
+    }
+}
+
+#[test]
+#[async]
+// Conditions of `else if` are only evaluated once the ones before them are false
+fn test_lazy_conditions() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(classify(0, log.clone())), "zero");
+    assert!(log.borrow().is_empty());
+    assert_eq!(await!(classify(200, log.clone())), "large");
+    assert_eq!(await!(classify(-1, log.clone())), "negative");
+    assert_eq!(await!(classify(5, log.clone())), "small");
+    assert_eq!(*log.borrow(), vec![200, 1, -5]);
+
+    let mut i = 0;
+    while i < 10 && await!(logged(i, log.clone())) || i == 0 {
+        i += 1;
+    }
+    assert_eq!(i, 10);
+}
+
+#[async]
+fn describe(value: Option<i32>, log: Rc<RefCell<Vec<i32>>>) -> String {
+    match value {
+        None => "none".to_string(),
+        Some(0) => "zero".to_string(),
+        Some(n) if await!(logged(n - 10, log.clone())) => format!("over ten by {}", n - 10),
+        Some(n) if n % 2 == 0 => "even".to_string(),
+        Some(n) if await!(logged(n, log.clone())) => format!("odd {}", n),
+        Some(_) => "negative odd".to_string(),
+    }
+}
+
+#[test]
+#[async]
+// Guards that await only run once the value gets to their arm
+fn test_lazy_guards() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(describe(None, log.clone())), "none");
+    assert_eq!(await!(describe(Some(0), log.clone())), "zero");
+    assert!(log.borrow().is_empty());
+
+    assert_eq!(await!(describe(Some(15), log.clone())), "over ten by 5");
+    assert_eq!(await!(describe(Some(4), log.clone())), "even");
+    assert_eq!(await!(describe(Some(3), log.clone())), "odd 3");
+    assert_eq!(await!(describe(Some(-3), log.clone())), "negative odd");
+    assert_eq!(*log.borrow(), vec![5, -6, -7, 3, -13, -3]);
+}
+
+#[async]
+fn describe_name(name: Option<String>, log: Rc<RefCell<Vec<i32>>>) -> String {
+    let label = match name {
+        None => "none".to_string(),
+        Some(ref n) if await!(logged(n.len() as i32 - 3, log.clone())) => format!("long {}", n),
+        Some(ref n) if n.is_empty() => "empty".to_string(),
+        Some(ref n) => format!("short {}", n),
+    };
+    // Arms binding by reference leave the matched variable usable
+    format!("{}, {:?}", label, name)
+}
+
+#[async]
+fn describe_len(name: String, log: Rc<RefCell<Vec<i32>>>) -> &'static str {
+    // Values that aren't places are matched once and moved into the nested matches
+    match name.len() {
+        0 => "empty",
+        n if await!(logged(n as i32 - 3, log.clone())) => "long",
+        _ => "short",
+    }
+}
+
+#[async]
+fn take_name(name: Option<String>, log: Rc<RefCell<Vec<i32>>>) -> String {
+    // Bindings that aren't `Copy` are references in the guard and moved into the body
+    match name {
+        Some(n) if await!(logged(n.len() as i32 - 3, log.clone())) => n,
+        _ => "nobody".to_string(),
+    }
+}
+
+#[test]
+#[async]
+fn test_lazy_guard_place() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(await!(describe_name(Some("long".to_string()), log.clone())), "long long, Some(\"long\")");
+    assert_eq!(await!(describe_name(Some("ab".to_string()), log.clone())), "short ab, Some(\"ab\")");
+    assert_eq!(await!(describe_name(Some(String::new()), log.clone())), "empty, Some(\"\")");
+    assert_eq!(await!(describe_name(None, log.clone())), "none, None");
+    assert_eq!(*log.borrow(), vec![1, -1, -3]);
+
+    assert_eq!(await!(describe_len(String::new(), log.clone())), "empty");
+    assert_eq!(await!(describe_len("long".to_string(), log.clone())), "long");
+    assert_eq!(await!(describe_len("ab".to_string(), log.clone())), "short");
+    assert_eq!(*log.borrow(), vec![1, -1, -3, 1, -1]);
+
+    assert_eq!(await!(take_name(Some("long".to_string()), log.clone())), "long");
+    assert_eq!(await!(take_name(Some("ab".to_string()), log.clone())), "nobody");
+}
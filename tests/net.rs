@@ -0,0 +1,171 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::net::{TcpListener, TcpStream, UdpSocket, UnixListener, UnixStream};
+use async_runtime::pipe;
+use std::io;
+use std::net::{Shutdown, SocketAddr};
+
+#[async('static)]
+fn echo_once(listener: TcpListener) -> io::Result<usize> {
+    let (stream, _) = await!(listener.accept())?;
+    let mut echoed = 0;
+    loop {
+        let data = await!(stream.clone().read(4))?;
+        if data.is_empty() {
+            return Ok(echoed);
+        }
+        echoed += data.len();
+        await!(stream.clone().write_all(data))?;
+    }
+}
+
+#[async('static)]
+fn send_and_receive(addr: SocketAddr, message: Vec<u8>) -> io::Result<Vec<u8>> {
+    let stream = await!(TcpStream::connect(addr))?;
+    await!(stream.clone().write_all(message))?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut received = Vec::new();
+    loop {
+        let data = await!(stream.clone().read(1024))?;
+        if data.is_empty() {
+            return Ok(received);
+        }
+        received.extend(data);
+    }
+}
+
+#[async('static)]
+fn echo_over_tcp() -> io::Result<(usize, Vec<u8>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let (echoed, received) = join!(echo_once(listener), send_and_receive(addr, b"hello world".to_vec()));
+    Ok((echoed?, received?))
+}
+
+#[test]
+fn test_tcp_echo() {
+    let (echoed, received) = async_runtime::block_on(echo_over_tcp).unwrap();
+    assert_eq!(echoed, 11);
+    assert_eq!(received, b"hello world");
+}
+
+#[test]
+fn test_connection_refused() {
+    // Nobody listens on the port once the listener is dropped
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let result = async_runtime::block_on(|callback| TcpStream::connect(addr, callback));
+    assert_eq!(result.err().map(|err| err.kind()), Some(io::ErrorKind::ConnectionRefused));
+}
+
+#[async('static)]
+fn ping_over_udp() -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let a = UdpSocket::bind("127.0.0.1:0")?;
+    let b = UdpSocket::bind("127.0.0.1:0")?;
+    let (a_addr, b_addr) = (a.local_addr()?, b.local_addr()?);
+
+    let (received, sent) = join!(b.recv_from(16), a.send_to(b"ping".to_vec(), b_addr));
+    let (ping, from) = received?;
+    sent?;
+    assert_eq!(from, a_addr);
+
+    await!(b.send_to(b"pong".to_vec(), a_addr))?;
+    let (pong, _) = await!(a.recv_from(16))?;
+    Ok((ping, pong))
+}
+
+#[test]
+fn test_udp() {
+    let (ping, pong) = async_runtime::block_on(ping_over_udp).unwrap();
+    assert_eq!((&ping[..], &pong[..]), (&b"ping"[..], &b"pong"[..]));
+}
+
+#[async('static)]
+fn ping_connected_udp() -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let a = UdpSocket::bind("127.0.0.1:0")?;
+    let b = UdpSocket::bind("127.0.0.1:0")?;
+    a.connect(b.local_addr()?)?;
+    b.connect(a.local_addr()?)?;
+    assert_eq!(a.peer_addr()?, b.local_addr()?);
+
+    let (received, sent) = join!(b.recv(16), a.send(b"ping".to_vec()));
+    let ping = received?;
+    sent?;
+    await!(b.send(b"pong".to_vec()))?;
+    let pong = await!(a.recv(16))?;
+    Ok((ping, pong))
+}
+
+#[test]
+// Connected sockets send to and receive from their peer
+fn test_connected_udp() {
+    let (ping, pong) = async_runtime::block_on(ping_connected_udp).unwrap();
+    assert_eq!((&ping[..], &pong[..]), (&b"ping"[..], &b"pong"[..]));
+}
+
+#[async('static)]
+fn greet_over_unix_socket(path: std::path::PathBuf) -> io::Result<Vec<u8>> {
+    let listener = UnixListener::bind(&path)?;
+    let (server, client) = join!(listener.accept(), UnixStream::connect(path));
+    let (server, client) = (server?, client?);
+
+    await!(server.clone().write_all(b"hi".to_vec()))?;
+    server.shutdown(Shutdown::Write)?;
+    await!(client.read(16))
+}
+
+#[test]
+fn test_unix_socket() {
+    let path = std::env::temp_dir().join(format!("async-runtime-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let greeting = async_runtime::block_on(|callback| greet_over_unix_socket(path.clone(), callback));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(greeting.unwrap(), b"hi");
+}
+
+#[async('static)]
+fn read_to_end(reader: pipe::PipeReader) -> io::Result<usize> {
+    let mut total = 0;
+    loop {
+        let data = await!(reader.clone().read(64 * 1024))?;
+        if data.is_empty() {
+            return Ok(total);
+        }
+        total += data.len();
+    }
+}
+
+#[async('static)]
+fn write_and_close(writer: pipe::PipeWriter, data: Vec<u8>) -> io::Result<()> {
+    // Dropping the writer closes the pipe
+    await!(writer.write_all(data))
+}
+
+#[async('static)]
+fn fill_pipe() -> io::Result<usize> {
+    let (reader, writer) = pipe::pipe()?;
+    // More than fits into the buffer of the pipe, writing has to wait for the reader
+    let (read, written) = join!(read_to_end(reader), write_and_close(writer, vec![7; 1 << 20]));
+    written?;
+    read
+}
+
+#[test]
+fn test_pipe() {
+    assert_eq!(async_runtime::block_on(fill_pipe).unwrap(), 1 << 20);
+}
+
+#[async('static)]
+fn unix_pair() -> io::Result<Vec<u8>> {
+    let (a, b) = UnixStream::pair()?;
+    let (received, sent) = join!(b.read(16), a.write(b"pair".to_vec()));
+    assert_eq!(sent?, 4);
+    received
+}
+
+#[test]
+fn test_unix_pair() {
+    assert_eq!(async_runtime::block_on(unix_pair).unwrap(), b"pair");
+}
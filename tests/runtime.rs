@@ -0,0 +1,157 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+#[async]
+fn count(name: &'static str, times: i32, log: Rc<RefCell<Vec<String>>>) {
+    for i in 0..times {
+        log.borrow_mut().push(format!("{}{}", name, i));
+        await!(async_runtime::yield_now());
+    }
+}
+
+#[test]
+// Functions waiting on the event loop take turns
+fn test_interleaving() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (a, b) = (log.clone(), log.clone());
+    async_runtime::run(move || {
+        count("a", 3, a);
+        count("b", 2, b);
+    });
+
+    assert_eq!(*log.borrow(), vec!["a0", "b0", "a1", "b1", "a2"]);
+}
+
+#[async('static)]
+fn countdown(n: u32) -> u32 {
+    let mut n = n;
+    while n > 0 {
+        await!(async_runtime::yield_now());
+        n -= 1;
+    }
+    n
+}
+
+#[test]
+// Every callback runs from the event loop, deep chains don't grow the stack
+fn test_long_chain() {
+    assert_eq!(async_runtime::block_on(|callback| countdown(1_000_000, callback)), 0);
+}
+
+// Leaf operation completed by another thread
+fn double_later(n: u64, callback: impl FnOnce(u64) + 'static) {
+    let completer = async_runtime::pending(callback).remote();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(n));
+        completer.complete(n * 2);
+    });
+}
+
+#[async('static)]
+fn sum_doubled(items: Vec<u64>) -> u64 {
+    let mut sum = 0;
+    for item in items {
+        sum += await!(double_later(item));
+    }
+    sum
+}
+
+#[test]
+// The event loop waits for operations completed by other threads
+fn test_remote_completion() {
+    assert_eq!(async_runtime::block_on(|callback| sum_doubled(vec![3, 1, 2], callback)), 12);
+}
+
+#[async('static)]
+fn double_or_give_up(n: u64) -> Option<u64> {
+    select! {
+        doubled = double_later(n) => Some(doubled),
+        _ = async_runtime::yield_now() => None,
+    }
+}
+
+#[test]
+// The operation that loses the race still completes, but its result is ignored
+fn test_select_timeout() {
+    assert_eq!(async_runtime::block_on(|callback| double_or_give_up(20, callback)), None);
+}
+
+// Completes on a later turn of the event loop
+fn add_later(a: i32, b: i32, callback: impl FnOnce(i32) + 'static) {
+    let completer = async_runtime::pending(callback);
+    async_runtime::schedule(move || completer.complete(a + b));
+}
+
+#[async('static)]
+fn fib(n: i32) -> i32 {
+    let (mut a, mut b) = (0, 1);
+    for _ in 0..n {
+        let next = await!(add_later(a, b));
+        a = b;
+        b = next;
+    }
+    a
+}
+
+#[test]
+fn test_local_completion() {
+    assert_eq!(async_runtime::block_on(|callback| fib(10, callback)), 55);
+}
+
+#[test]
+// A dropped completer doesn't keep the event loop running
+fn test_dropped_completer() {
+    let called = Rc::new(RefCell::new(false));
+    let flag = called.clone();
+    async_runtime::run(move || {
+        let completer = async_runtime::pending(move |_: ()| *flag.borrow_mut() = true).remote();
+        thread::spawn(move || drop(completer));
+    });
+
+    assert!(!*called.borrow());
+}
+
+#[test]
+#[should_panic(expected = "no event loop is running")]
+fn test_schedule_outside_of_loop() {
+    async_runtime::schedule(|| {});
+}
+
+fn busy_sum(n: u64) -> u64 {
+    thread::sleep(Duration::from_millis(30));
+    (1..=n).sum()
+}
+
+#[async('static)]
+fn sum_in_background(n: u64, log: Rc<RefCell<Vec<String>>>) -> u64 {
+    let sum = await!(async_runtime::spawn_blocking(move || busy_sum(n)));
+    log.borrow_mut().push("sum".to_string());
+    sum
+}
+
+#[test]
+// Blocking work doesn't hold up the other callbacks on the event loop
+fn test_spawn_blocking() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (a, b) = (log.clone(), log.clone());
+    let sum = async_runtime::block_on(move |callback| {
+        sum_in_background(100, a, callback);
+        count("tick", 3, b);
+    });
+
+    assert_eq!(sum, 5050);
+    assert_eq!(*log.borrow(), vec!["tick0", "tick1", "tick2", "sum"]);
+}
+
+#[test]
+#[should_panic(expected = "work failed")]
+// Panics of the work are resumed on the event loop
+fn test_spawn_blocking_panic() {
+    async_runtime::block_on(|callback| async_runtime::spawn_blocking(|| panic!("work failed"), callback))
+}
@@ -0,0 +1,180 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::{CancellationToken, Scope};
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[async('static)]
+fn square_later(i: u64) -> io::Result<u64> {
+    // Later children complete first
+    await!(async_runtime::sleep(millis(20 - i * 5)));
+    Ok(i * i)
+}
+
+#[async('static)]
+fn squares() -> io::Result<Vec<u64>> {
+    await!(async_runtime::scope(|scope| {
+        for i in 0..4 {
+            scope.spawn(move |callback| square_later(i, callback));
+        }
+    }))
+}
+
+#[test]
+// The values are in the order the children were spawned
+fn test_scope_values() {
+    assert_eq!(async_runtime::block_on(squares).unwrap(), vec![0, 1, 4, 9]);
+}
+
+#[async('static)]
+fn fail_later(ms: u64, log: Log) -> io::Result<u32> {
+    await!(async_runtime::sleep(millis(ms)));
+    log.borrow_mut().push(format!("failed after {}", ms));
+    Err(io::Error::other("child failed"))
+}
+
+#[async('static, cancellable)]
+fn work_forever(token: CancellationToken, log: Log) -> io::Result<u32> {
+    loop {
+        await!(async_runtime::sleep(millis(2)));
+        if log.borrow().iter().any(|entry| entry.starts_with("failed")) {
+            log.borrow_mut().push("still working".to_string());
+        }
+    }
+}
+
+#[async('static)]
+fn fail_in_scope(log: Log) -> io::Result<Vec<u32>> {
+    let children = log.clone();
+    let result = await!(async_runtime::scope(move |scope| {
+        let log = children.clone();
+        scope.spawn_cancellable(move |token, callback| work_forever(token, log, callback));
+        let log = children.clone();
+        scope.spawn(move |callback| fail_later(10, log, callback));
+        let log = children;
+        scope.spawn_cancellable(move |token, callback| work_forever(token, log, callback));
+    }));
+    log.borrow_mut().push("scope done".to_string());
+    result
+}
+
+#[test]
+// A failing child cancels its siblings, the error reaches the parent
+fn test_failure_cancels_siblings() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let err = async_runtime::block_on(|callback| fail_in_scope(log.clone(), callback)).unwrap_err();
+    assert_eq!(err.to_string(), "child failed");
+    // The siblings stop at their next await, before anything else runs
+    assert_eq!(*log.borrow(), vec!["failed after 10", "scope done"]);
+}
+
+#[async('static)]
+fn fail_next_to_slow_child(log: Log) -> io::Result<Vec<u32>> {
+    let children = log.clone();
+    let result = await!(async_runtime::scope(move |scope| {
+        let log = children.clone();
+        scope.spawn(move |callback| fail_later(5, log, callback));
+        let log = children;
+        scope.spawn(move |callback| fail_later(20, log, callback));
+    }));
+    log.borrow_mut().push("scope done".to_string());
+    result
+}
+
+#[test]
+// The scope doesn't wait for plain children once one failed, the first error is the one reported.
+// The other children keep running until they complete.
+fn test_failure_stops_waiting_for_children() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert!(async_runtime::block_on(|callback| fail_next_to_slow_child(log.clone(), callback)).is_err());
+    assert_eq!(*log.borrow(), vec!["failed after 5", "scope done", "failed after 20"]);
+}
+
+#[async('static)]
+fn succeed_later(ms: u64, log: Log) -> io::Result<u32> {
+    await!(async_runtime::sleep(millis(ms)));
+    log.borrow_mut().push(format!("succeeded after {}", ms));
+    Ok(0)
+}
+
+#[async('static)]
+fn fail_next_to_both_kinds(log: Log) -> io::Result<Vec<u32>> {
+    let children = log.clone();
+    let result = await!(async_runtime::scope(move |scope| {
+        let log = children.clone();
+        scope.spawn(move |callback| fail_later(5, log, callback));
+        let log = children.clone();
+        scope.spawn(move |callback| succeed_later(15, log, callback));
+        let log = children;
+        scope.spawn_cancellable(move |token, callback| work_forever(token, log, callback));
+    }));
+    log.borrow_mut().push("scope done".to_string());
+    result
+}
+
+#[test]
+// Children of both kinds are cancelled by a failing sibling, only the ones
+// spawned with `spawn_cancellable` stop their work
+fn test_failure_cancels_both_kinds() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let err = async_runtime::block_on(|callback| fail_next_to_both_kinds(log.clone(), callback)).unwrap_err();
+    assert_eq!(err.to_string(), "child failed");
+    assert_eq!(*log.borrow(), vec!["failed after 5", "scope done", "succeeded after 15"]);
+}
+
+#[async('static)]
+fn spawn_more(scope: Scope<u64, io::Error>, depth: u64) -> io::Result<u64> {
+    await!(async_runtime::yield_now());
+    if depth < 3 {
+        let nested = scope.clone();
+        scope.spawn(move |callback| spawn_more(nested, depth + 1, callback));
+    }
+    Ok(depth)
+}
+
+#[async('static)]
+fn nested_children() -> io::Result<Vec<u64>> {
+    await!(async_runtime::scope(|scope| {
+        let nested = scope.clone();
+        scope.spawn(move |callback| spawn_more(nested, 0, callback));
+    }))
+}
+
+#[test]
+// Children can spawn more children into the scope while it's open
+fn test_nested_spawn() {
+    assert_eq!(async_runtime::block_on(nested_children).unwrap(), vec![0, 1, 2, 3]);
+}
+
+#[async('static, cancellable)]
+fn cancel_self(token: CancellationToken) -> io::Result<()> {
+    token.cancel();
+    await!(async_runtime::yield_now());
+    Ok(())
+}
+
+#[async('static)]
+fn cancelled_without_failure() -> io::Result<Vec<()>> {
+    await!(async_runtime::scope(|scope| {
+        scope.spawn_cancellable(cancel_self);
+    }))
+}
+
+#[test]
+fn test_empty_and_cancelled_scopes() {
+    let empty = async_runtime::block_on(|callback| async_runtime::scope(|_: &Scope<(), io::Error>| {}, callback));
+    assert_eq!(empty.unwrap(), vec![]);
+
+    let err = async_runtime::block_on(cancelled_without_failure).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+}
@@ -0,0 +1,159 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::{async, async_stream};
+use async_runtime::stream::AsyncStream;
+use std::cell::RefCell;
+use std::num::ParseIntError;
+use std::rc::Rc;
+use std::time::Duration;
+
+#[async('static)]
+fn collect<T: 'static>(stream: AsyncStream<T>) -> Vec<T> {
+    let mut values = Vec::new();
+    while let Some(value) = await!(stream.clone().next()) {
+        values.push(value);
+    }
+    values
+}
+
+#[async_stream]
+fn countdown(from: u32) -> u32 {
+    for i in (1..=from).rev() {
+        await!(async_runtime::sleep(Duration::from_millis(1)));
+        r#yield!(i);
+    }
+}
+
+#[test]
+fn test_yield_in_loop() {
+    assert_eq!(async_runtime::block_on(|callback| collect(countdown(3), callback)), vec![3, 2, 1]);
+}
+
+struct Page {
+    items: Vec<u32>,
+    next: Option<u32>,
+}
+
+#[async('static)]
+fn get_page(number: u32) -> Page {
+    await!(async_runtime::yield_now());
+    let next = if number < 2 { Some(number + 1) } else { None };
+    Page { items: vec![number * 10, number * 10 + 1], next }
+}
+
+#[async_stream]
+fn all_items() -> u32 {
+    let mut next = Some(0);
+    while let Some(number) = next {
+        let Page { items, next: following } = await!(get_page(number));
+        for item in items {
+            r#yield!(item);
+        }
+        next = following;
+    }
+}
+
+#[test]
+// Values are yielded from nested loops, the rebound callback is passed along by both of them
+fn test_paginated() {
+    assert_eq!(async_runtime::block_on(|callback| collect(all_items(), callback)),
+               vec![0, 1, 10, 11, 20, 21]);
+}
+
+#[async_stream]
+fn parse_all(texts: Vec<&'static str>) -> Result<u32, ParseIntError> {
+    for text in texts {
+        r#yield!(Ok(text.parse()?));
+    }
+}
+
+#[test]
+// An error returned with `?` is the last value
+fn test_error_ends_stream() {
+    let values = async_runtime::block_on(|callback| collect(parse_all(vec!["1", "x", "3"]), callback));
+    assert_eq!(values.len(), 2);
+    assert_eq!(values[0], Ok(1));
+    assert!(values[1].is_err());
+}
+
+#[async_stream]
+fn describe(values: Vec<Option<u32>>) -> String {
+    for value in values {
+        match value {
+            Some(value) if value > 10 => r#yield!(format!("big {}", value)),
+            Some(value) => {
+                if value == 0 {
+                    continue;
+                }
+                r#yield!(format!("small {}", value));
+            }
+            None => return "none".to_string(),
+        }
+    }
+}
+
+#[test]
+// `return value` yields it last
+fn test_yield_in_branches() {
+    let stream = describe(vec![Some(0), Some(5), Some(50), None, Some(1)]);
+    assert_eq!(async_runtime::block_on(|callback| collect(stream, callback)),
+               vec!["small 5", "big 50", "none"]);
+}
+
+#[async_stream]
+fn naturals(log: Rc<RefCell<Vec<u32>>>) -> u32 {
+    let mut i = 0;
+    loop {
+        log.borrow_mut().push(i);
+        r#yield!(i);
+        i += 1;
+    }
+}
+
+#[async('static)]
+fn first_two(stream: AsyncStream<u32>) -> (u32, u32) {
+    let a = await!(stream.clone().next());
+    let b = await!(stream.clone().next());
+    (a.unwrap(), b.unwrap())
+}
+
+#[test]
+// The body only runs up to the value that was asked for
+fn test_lazy() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let stream = naturals(log.clone());
+    assert!(log.borrow().is_empty());
+
+    assert_eq!(async_runtime::block_on(|callback| first_two(stream, callback)), (0, 1));
+    assert_eq!(*log.borrow(), vec![0, 1]);
+}
+
+#[async_stream]
+fn count_to(n: u32) -> u32 {
+    let mut i = 0;
+    while i < n {
+        i += 1;
+        r#yield!(i);
+    }
+}
+
+#[test]
+// Consuming a stream that never waits takes a turn of the event loop per value
+fn test_long_stream() {
+    let values = async_runtime::block_on(|callback| collect(count_to(100_000), callback));
+    assert_eq!(values.len(), 100_000);
+}
+
+#[test]
+// Streams can be built from iterators, clones of a stream share its values
+fn test_iter() {
+    let stream = AsyncStream::iter(vec![1, 2, 3]);
+    let first = async_runtime::block_on(|callback| stream.next(callback));
+    assert_eq!(first, Some(1));
+    assert_eq!(async_runtime::block_on(|callback| collect(stream.clone(), callback)), vec![2, 3]);
+    assert!(stream.is_done());
+
+    let empty = AsyncStream::<u32>::empty();
+    assert_eq!(async_runtime::block_on(|callback| collect(empty, callback)), Vec::<u32>::new());
+}
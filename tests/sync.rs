@@ -0,0 +1,202 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::sync::{Mutex, Notify, RwLock, Semaphore};
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::time::Duration;
+
+type Log = Rc<RefCell<Vec<String>>>;
+
+#[async]
+fn increment(counter: Mutex<u32>) {
+    let mut count = await!(counter.lock());
+    let value = *count;
+    // Everyone else reads the value in between without the lock
+    await!(async_runtime::yield_now());
+    *count = value + 1;
+}
+
+#[async('static)]
+fn get(counter: Mutex<u32>) -> u32 {
+    let count = await!(counter.lock());
+    *count
+}
+
+#[test]
+fn test_mutex_held_across_await() {
+    let counter = Mutex::new(0);
+    let incremented = counter.clone();
+    async_runtime::run(move || {
+        for _ in 0..3 {
+            increment(incremented.clone());
+        }
+    });
+    assert_eq!(async_runtime::block_on(|callback| get(counter, callback)), 3);
+}
+
+#[async]
+fn take_turns(name: &'static str, mutex: Mutex<Vec<&'static str>>) {
+    for _ in 0..2 {
+        let mut turns = await!(mutex.clone().lock());
+        turns.push(name);
+        await!(async_runtime::yield_now());
+    }
+}
+
+#[test]
+// The lock goes to the waiters in order, a holder that locks again queues up behind them
+fn test_mutex_fairness() {
+    let mutex = Mutex::new(Vec::new());
+    let shared = mutex.clone();
+    async_runtime::run(move || {
+        take_turns("a", shared.clone());
+        take_turns("b", shared.clone());
+        take_turns("c", shared);
+    });
+    assert_eq!(*mutex.try_lock().unwrap(), vec!["a", "b", "c", "a", "b", "c"]);
+}
+
+#[async]
+fn half_update(mutex: Mutex<u32>) {
+    let mut value = await!(mutex.lock());
+    *value = 1;
+    await!(async_runtime::yield_now());
+    panic!("failed halfway");
+}
+
+#[test]
+// The guard is dropped by the panic, the lock isn't poisoned
+fn test_mutex_not_poisoned() {
+    let mutex = Mutex::new(0);
+    let updated = mutex.clone();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| async_runtime::run(move || half_update(updated))));
+    assert!(result.is_err());
+    assert_eq!(async_runtime::block_on(|callback| get(mutex, callback)), 1);
+}
+
+#[async('static)]
+fn hold(mutex: Mutex<u32>) {
+    let mut value = await!(mutex.lock());
+    await!(async_runtime::sleep(Duration::from_millis(50)));
+    *value += 1;
+}
+
+#[async('static)]
+fn fail_later() {
+    await!(async_runtime::yield_now());
+    panic!("failed");
+}
+
+#[test]
+// The pending sleep holding the guard is dropped after the event loop is gone,
+// which gives the lock back and drops the waiter
+fn test_guard_dropped_after_panic() {
+    let mutex = Mutex::new(0);
+    let (held, waiting) = (mutex.clone(), mutex.clone());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        async_runtime::run(move || {
+            hold(held);
+            increment(waiting);
+            fail_later();
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(async_runtime::block_on(|callback| get(mutex, callback)), 0);
+}
+
+#[async]
+fn read_value(lock: RwLock<u32>, log: Log) {
+    let value = await!(lock.read());
+    log.borrow_mut().push(format!("read {}", *value));
+    await!(async_runtime::yield_now());
+    log.borrow_mut().push("read done".to_string());
+}
+
+#[async]
+fn write_value(lock: RwLock<u32>, value: u32, log: Log) {
+    let mut guard = await!(lock.write());
+    *guard = value;
+    log.borrow_mut().push(format!("write {}", value));
+    await!(async_runtime::yield_now());
+    log.borrow_mut().push("write done".to_string());
+}
+
+#[test]
+// Readers share the lock, the reader that comes after a waiting writer waits for it
+fn test_rwlock() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let lock = RwLock::new(0);
+    let (shared, events) = (lock.clone(), log.clone());
+    async_runtime::run(move || {
+        read_value(shared.clone(), events.clone());
+        read_value(shared.clone(), events.clone());
+        write_value(shared.clone(), 1, events.clone());
+        read_value(shared, events);
+    });
+    assert_eq!(*log.borrow(), vec!["read 0", "read 0", "read done", "read done",
+                                   "write 1", "write done", "read 1", "read done"]);
+    assert!(lock.try_write().is_some());
+}
+
+#[async]
+fn limited(semaphore: Semaphore, running: Rc<RefCell<(u32, u32)>>) {
+    let _permit = await!(semaphore.acquire());
+    {
+        let mut running = running.borrow_mut();
+        running.0 += 1;
+        running.1 = running.1.max(running.0);
+    }
+    await!(async_runtime::yield_now());
+    running.borrow_mut().0 -= 1;
+}
+
+#[test]
+fn test_semaphore() {
+    // Number running right now and the most that ever ran at once
+    let running = Rc::new(RefCell::new((0, 0)));
+    let semaphore = Semaphore::new(2);
+    let (shared, counts) = (semaphore.clone(), running.clone());
+    async_runtime::run(move || {
+        for _ in 0..5 {
+            limited(shared.clone(), counts.clone());
+        }
+    });
+    assert_eq!(*running.borrow(), (0, 2));
+    assert_eq!(semaphore.available_permits(), 2);
+
+    let permit = semaphore.try_acquire_many(2).unwrap();
+    assert!(semaphore.try_acquire().is_none());
+    permit.forget();
+    assert_eq!(semaphore.available_permits(), 0);
+}
+
+#[async]
+fn wait_for(notify: Notify, name: &'static str, log: Log) {
+    await!(notify.notified());
+    log.borrow_mut().push(name.to_string());
+}
+
+#[test]
+fn test_notify() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let events = log.clone();
+    async_runtime::run(move || {
+        let notify = Notify::new();
+        // Only one waiter gets through without waiting
+        notify.notify_one();
+        notify.notify_one();
+        wait_for(notify.clone(), "first", events.clone());
+        wait_for(notify.clone(), "second", events.clone());
+        wait_for(notify.clone(), "third", events.clone());
+
+        async_runtime::schedule(move || {
+            notify.notify_waiters();
+            // Not notified by the earlier call
+            wait_for(notify, "late", events);
+        });
+    });
+    assert_eq!(*log.borrow(), vec!["first", "second", "third"]);
+}
@@ -0,0 +1,120 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::{CancellationToken, JoinError, JoinHandle};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+type Log = Rc<RefCell<Vec<&'static str>>>;
+
+#[async('static)]
+fn add_one_later(i: i32, log: Log) -> i32 {
+    log.borrow_mut().push("task started");
+    await!(async_runtime::yield_now());
+    log.borrow_mut().push("task done");
+    i + 1
+}
+
+#[async('static)]
+fn spawn_and_join(log: Log) -> Result<i32, JoinError> {
+    let task_log = log.clone();
+    let handle = async_runtime::spawn(move |callback| add_one_later(1, task_log, callback));
+    // The task starts on the next turn
+    log.borrow_mut().push("spawned");
+    assert!(!handle.is_finished());
+    await!(handle.join())
+}
+
+#[test]
+fn test_spawn_and_join() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(async_runtime::block_on(|callback| spawn_and_join(log.clone(), callback)), Ok(2));
+    assert_eq!(*log.borrow(), vec!["spawned", "task started", "task done"]);
+}
+
+#[async('static)]
+fn finish_before_join(log: Log) -> bool {
+    let handle = async_runtime::spawn(move |callback| add_one_later(1, log, callback));
+    await!(async_runtime::sleep(Duration::from_millis(5)));
+    let finished = handle.is_finished();
+    // The result is kept until the handle is awaited
+    assert_eq!(await!(handle.join()), Ok(2));
+    finished
+}
+
+#[test]
+fn test_is_finished() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert!(async_runtime::block_on(|callback| finish_before_join(log.clone(), callback)));
+}
+
+#[test]
+// Dropping the handle doesn't stop the task
+fn test_detached() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let task_log = log.clone();
+    async_runtime::run(move || {
+        async_runtime::spawn(move |callback| add_one_later(1, task_log, callback));
+    });
+    assert_eq!(*log.borrow(), vec!["task started", "task done"]);
+}
+
+#[test]
+// A task aborted before it started never runs
+fn test_abort_before_start() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let task_log = log.clone();
+    let result = async_runtime::block_on(move |callback| {
+        let handle: JoinHandle<i32> = async_runtime::spawn(move |callback| add_one_later(1, task_log, callback));
+        handle.abort();
+        assert!(!handle.is_finished());
+        handle.join(callback);
+    });
+    assert_eq!(result, Err(JoinError));
+    assert!(log.borrow().is_empty());
+}
+
+#[async('static)]
+fn abort_while_joining(log: Log) -> Result<i32, JoinError> {
+    let handle = async_runtime::spawn(move |callback| add_one_later(1, log, callback));
+    let abort = handle.abort_handle();
+    async_runtime::schedule(move || abort.abort());
+    await!(handle.join())
+}
+
+#[test]
+// The continuations of a running task still run, but its result is dropped
+fn test_abort_while_joining() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(async_runtime::block_on(|callback| abort_while_joining(log.clone(), callback)), Err(JoinError));
+    assert_eq!(*log.borrow(), vec!["task started", "task done"]);
+}
+
+#[async('static, cancellable)]
+fn sleep_then_log(token: CancellationToken, log: Log) -> i32 {
+    log.borrow_mut().push("task started");
+    await!(async_runtime::sleep(Duration::from_millis(20)));
+    log.borrow_mut().push("task done");
+    1
+}
+
+#[async('static)]
+fn abort_while_suspended(log: Log) -> Result<i32, JoinError> {
+    let task_log = log.clone();
+    let handle = async_runtime::spawn_cancellable(move |token, callback| sleep_then_log(token, task_log, callback));
+    // The task is waiting for its sleep by the time it is aborted
+    await!(async_runtime::sleep(Duration::from_millis(5)));
+    handle.abort();
+    log.borrow_mut().push("aborted");
+    await!(handle.join())
+}
+
+#[test]
+// Aborting a cancellable task cancels its token, it stops at the await it is suspended in
+fn test_abort_while_suspended() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    assert_eq!(async_runtime::block_on(|callback| abort_while_suspended(log.clone(), callback)), Err(JoinError));
+    assert_eq!(*log.borrow(), vec!["task started", "aborted"]);
+}
@@ -0,0 +1,94 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::Elapsed;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+fn millis(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[async]
+fn wake_after(ms: u64, log: Rc<RefCell<Vec<u64>>>) {
+    await!(async_runtime::sleep(millis(ms)));
+    log.borrow_mut().push(ms);
+}
+
+#[test]
+// Timers fire in the order of their deadlines, never early
+fn test_sleep() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let (a, b, c) = (log.clone(), log.clone(), log.clone());
+    let start = Instant::now();
+    async_runtime::run(move || {
+        wake_after(30, a);
+        wake_after(10, b);
+        wake_after(20, c);
+    });
+
+    assert_eq!(*log.borrow(), vec![10, 20, 30]);
+    assert!(start.elapsed() >= millis(30));
+}
+
+#[test]
+// Deadlines further away than one round of the wheel
+fn test_long_sleep() {
+    let start = Instant::now();
+    async_runtime::block_on(|callback| async_runtime::sleep(millis(600), callback));
+    assert!(start.elapsed() >= millis(600));
+}
+
+#[async('static)]
+fn slow_add_one(i: i32, delay: u64) -> i32 {
+    await!(async_runtime::sleep(millis(delay)));
+    i + 1
+}
+
+#[async('static)]
+fn add_one_in_time(i: i32, delay: u64, limit: u64) -> Result<i32, Elapsed> {
+    await!(async_runtime::timeout(millis(limit), |callback| slow_add_one(i, delay, callback)))
+}
+
+#[test]
+fn test_timeout() {
+    let start = Instant::now();
+    assert_eq!(async_runtime::block_on(|callback| add_one_in_time(1, 5, 5_000, callback)), Ok(2));
+    // The timer is cancelled once the operation completed, the event loop doesn't wait for it
+    assert!(start.elapsed() < millis(5_000));
+
+    assert_eq!(async_runtime::block_on(|callback| add_one_in_time(1, 100, 50, callback)), Err(Elapsed));
+}
+
+#[async('static)]
+fn count_ticks(period: u64, ticks: u32) -> u32 {
+    let interval = async_runtime::interval(millis(period));
+    let mut count = 0;
+    while count < ticks {
+        await!(interval.tick());
+        count += 1;
+    }
+    count
+}
+
+#[test]
+fn test_interval() {
+    let start = Instant::now();
+    assert_eq!(async_runtime::block_on(|callback| count_ticks(10, 5, callback)), 5);
+    assert!(start.elapsed() >= millis(50));
+}
+
+#[async('static)]
+fn first_done() -> &'static str {
+    select! {
+        _ = async_runtime::sleep(millis(20)) => "slow",
+        _ = async_runtime::sleep(millis(5)) => "fast",
+    }
+}
+
+#[test]
+fn test_select_sleep() {
+    assert_eq!(async_runtime::block_on(first_done), "fast");
+}
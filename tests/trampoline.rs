@@ -0,0 +1,66 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[async]
+fn add_one(i: u64) -> u64 {
+    i + 1
+}
+
+#[async('static, trampoline)]
+fn count_to(n: u64) -> u64 {
+    let mut i = 0;
+    while i < n {
+        i = await!(add_one(i));
+    }
+    i
+}
+
+#[test]
+// Awaits that complete right away don't grow the stack
+fn test_long_synchronous_chain() {
+    let result = Rc::new(RefCell::new(None));
+    let slot = result.clone();
+    count_to(1_000_000, move |n| *slot.borrow_mut() = Some(n));
+    assert_eq!(*result.borrow(), Some(1_000_000));
+}
+
+#[async('static, trampoline)]
+fn count_even(n: u64) -> u64 {
+    let mut even = 0;
+    for i in 0..n {
+        if i % 2 == 1 {
+            continue;
+        }
+        even = await!(add_one(even));
+    }
+    even
+}
+
+#[test]
+// Iterations that skip the await restart the loop through the trampoline as well
+fn test_loop_without_await() {
+    let result = Rc::new(RefCell::new(None));
+    let slot = result.clone();
+    count_even(1_000_000, move |n| *slot.borrow_mut() = Some(n));
+    assert_eq!(*result.borrow(), Some(500_000));
+}
+
+#[async('static, trampoline)]
+fn sum_yielding(n: u64) -> u64 {
+    let mut sum = 0;
+    for i in 0..n {
+        await!(async_runtime::yield_now());
+        sum += await!(add_one(i));
+    }
+    sum
+}
+
+#[test]
+// Continuations completed by the event loop work the same way
+fn test_trampoline_on_event_loop() {
+    assert_eq!(async_runtime::block_on(|callback| sum_yielding(100, callback)), 5050);
+}
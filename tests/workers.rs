@@ -0,0 +1,38 @@
+extern crate async;
+extern crate async_runtime;
+
+use async::async;
+use async_runtime::spawn_blocking;
+use std::panic;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn nap() -> u32 {
+    thread::sleep(Duration::from_millis(40));
+    1
+}
+
+#[async('static)]
+fn four_naps() -> u32 {
+    let (a, b, c, d) = join!(spawn_blocking(nap), spawn_blocking(nap), spawn_blocking(nap), spawn_blocking(nap));
+    a + b + c + d
+}
+
+#[test]
+// The pool never runs more jobs at once than it has workers
+fn test_bounded_pool() {
+    async_runtime::set_worker_threads(2);
+
+    let start = Instant::now();
+    assert_eq!(async_runtime::block_on(four_naps), 4);
+    // Four jobs on two workers take two rounds
+    assert!(start.elapsed() >= Duration::from_millis(80));
+
+    // The pool is shared by the whole process, so the checks that need it
+    // running are made here rather than in tests of their own
+    assert!(panic::catch_unwind(|| async_runtime::set_worker_threads(8)).is_err());
+    let failed = panic::catch_unwind(|| {
+        async_runtime::block_on(|callback| spawn_blocking(|| -> u32 { panic!("failed on a worker") }, callback))
+    });
+    assert_eq!(failed.unwrap_err().downcast_ref::<&str>(), Some(&"failed on a worker"));
+}